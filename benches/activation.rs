@@ -0,0 +1,34 @@
+//! Measures the cost of a single `update_callback` as the number of slots grows.
+//! With activation tracked by a pending-slot counter the per-update time should stay
+//! flat rather than growing linearly with the slot count.
+//!
+//! Run with `cargo bench --bench activation`.
+
+use std::collections::HashSet;
+use std::hint::black_box;
+use std::time::Instant;
+
+use xtstate::XTState;
+
+fn main() {
+    for slot_count in [1_000, 10_000, 50_000] {
+        let names: Vec<String> = (0..slot_count).map(|i| format!("shard{}", i)).collect();
+        let mut xt_state = XTState::new();
+        xt_state.setup_slots(names.iter().cloned().collect::<HashSet<_>>(), false);
+
+        let updates = 100_000;
+        let start = Instant::now();
+        for i in 0..updates {
+            let name = names[i % slot_count].clone();
+            xt_state.update_callback(name, i % 3 != 0);
+            black_box(xt_state.is_activated());
+        }
+        let elapsed = start.elapsed();
+
+        println!(
+            "{:>6} slots: {:>8.1} ns/update",
+            slot_count,
+            elapsed.as_nanos() as f64 / updates as f64
+        );
+    }
+}
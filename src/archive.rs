@@ -0,0 +1,59 @@
+use std::collections::VecDeque;
+
+use crate::History;
+
+/// Histories of earlier runs, oldest first. A forced setup moves the current run's history
+/// here instead of discarding it.
+pub(crate) struct Archive<K, V = bool> {
+    runs: VecDeque<History<K, V>>,
+    max_runs: Option<usize>,
+    evicted: u64,
+}
+
+impl<K, V> Default for Archive<K, V> {
+    fn default() -> Self {
+        Archive {
+            runs: VecDeque::new(),
+            max_runs: None,
+            evicted: 0,
+        }
+    }
+}
+
+impl<K, V> Archive<K, V> {
+    pub(crate) fn push(&mut self, run: History<K, V>) {
+        self.runs.push_back(run);
+        self.trim();
+    }
+
+    pub(crate) fn get(&self, run: u64) -> Option<&History<K, V>> {
+        // Run ids only ever grow, so the archive is sorted by them.
+        let position = self
+            .runs
+            .binary_search_by_key(&run, |history| history.run())
+            .ok()?;
+        self.runs.get(position)
+    }
+
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = &History<K, V>> {
+        self.runs.iter()
+    }
+
+    pub(crate) fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    pub(crate) fn set_max_runs(&mut self, max_runs: Option<usize>) {
+        self.max_runs = max_runs;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        if let Some(max_runs) = self.max_runs {
+            while self.runs.len() > max_runs {
+                self.runs.pop_front();
+                self.evicted += 1;
+            }
+        }
+    }
+}
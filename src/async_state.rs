@@ -0,0 +1,266 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use tokio::sync::watch;
+
+use crate::observer::{DispatchQueue, Notice};
+use crate::{Identifier, SlotValue, XTState, XTStateError};
+
+struct Inner<K, V> {
+    state: XTState<K, V>,
+    versions: HashMap<K, u64>,
+}
+
+/// An async-aware shared `XTState`. The internal `std::sync::Mutex` is only held for the
+/// duration of a synchronous update or read and never across an `.await`; waiting is done
+/// on `tokio::sync::watch` channels, which makes every future returned here cancellation-safe.
+/// Listeners run outside the mutex in the order updates were applied, as with `SharedXTState`.
+pub struct AsyncXTState<K = Identifier, V = bool> {
+    inner: Mutex<Inner<K, V>>,
+    activated: watch::Sender<bool>,
+    updates: watch::Sender<u64>,
+    notices: DispatchQueue<K, V>,
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: SlotValue> AsyncXTState<K, V> {
+    pub fn new() -> Self {
+        AsyncXTState::from(XTState::new())
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: Clone + PartialEq + fmt::Debug> AsyncXTState<K, V> {
+    pub fn read<R>(&self, f: impl FnOnce(&XTState<K, V>) -> R) -> R {
+        f(&self.lock().state)
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.activated.subscribe()
+    }
+
+    crate::wrapper::mutating_api!();
+
+    pub async fn activated(&self) {
+        let mut receiver = self.activated.subscribe();
+        // The sender lives as long as `self`, so the channel cannot close while we wait.
+        let _ = receiver.wait_for(|&activated| activated).await;
+    }
+
+    /// Resolves with the slot's new value the next time an update changes it.
+    pub async fn slot_changed<Q>(&self, identifier: &Q) -> Result<V, XTStateError<K>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mut updates = self.updates.subscribe();
+        let version = self.slot_version(identifier)?;
+        loop {
+            let _ = updates.changed().await;
+            if self.slot_version(identifier)? != version {
+                return self
+                    .read(|xt| xt.get(identifier))
+                    .ok_or_else(|| XTStateError::UnknownSlot(identifier.to_owned()));
+            }
+        }
+    }
+
+    fn slot_version<Q>(&self, identifier: &Q) -> Result<u64, XTStateError<K>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let inner = self.lock();
+        if !inner.state.is_setup() {
+            return Err(XTStateError::NotSetUp);
+        }
+        if inner.state.get(identifier).is_none() {
+            return Err(XTStateError::UnknownSlot(identifier.to_owned()));
+        }
+        Ok(inner.versions.get(identifier).copied().unwrap_or_default())
+    }
+
+    fn write<R>(&self, f: impl FnOnce(&mut XTState<K, V>) -> R) -> R {
+        f(&mut self.lock().state)
+    }
+
+    fn apply_with(
+        &self,
+        f: impl FnOnce(&mut XTState<K, V>) -> Result<Notice<K, V>, XTStateError<K>>,
+    ) -> Result<(), XTStateError<K>> {
+        {
+            let mut inner = self.lock();
+            let notice = f(&mut inner.state)?;
+            for change in notice.changes() {
+                let slot = &*change.slot;
+                if inner.state.get(slot).is_none() {
+                    inner.versions.remove(slot);
+                } else if change.old != change.new {
+                    *inner.versions.entry(slot.clone()).or_default() += 1;
+                }
+            }
+            self.publish(&inner);
+            self.notices.push(&inner.state.observers, notice);
+        }
+        self.notices.drain();
+        Ok(())
+    }
+
+    /// Takes the locked state so that concurrent updates publish in the order they were
+    /// applied; sending on a `watch` channel never blocks.
+    fn publish(&self, inner: &Inner<K, V>) {
+        let activated = inner.state.is_activated();
+        self.activated.send_if_modified(|current| {
+            let modified = *current != activated;
+            *current = activated;
+            modified
+        });
+        self.updates.send_modify(|version| *version += 1);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner<K, V>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: SlotValue> Default for AsyncXTState<K, V> {
+    fn default() -> Self {
+        AsyncXTState::new()
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: Clone + PartialEq + fmt::Debug> From<XTState<K, V>>
+    for AsyncXTState<K, V>
+{
+    fn from(state: XTState<K, V>) -> Self {
+        let activated = state.is_activated();
+        AsyncXTState {
+            inner: Mutex::new(Inner {
+                state,
+                versions: HashMap::new(),
+            }),
+            activated: watch::Sender::new(activated),
+            updates: watch::Sender::new(0),
+            notices: DispatchQueue::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SlotSetup;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn async_with_slots() -> Arc<AsyncXTState> {
+        let state = Arc::new(AsyncXTState::new());
+        state.setup_slots(
+            HashSet::from(["slot1".to_string(), "slot2".to_string()]),
+            false,
+        );
+        state
+    }
+
+    #[tokio::test]
+    async fn test_activated_and_slot_changed() {
+        let state = async_with_slots();
+        let mut receiver = state.subscribe();
+        assert!(!*receiver.borrow_and_update());
+
+        let slot_waiter = {
+            let state = Arc::clone(&state);
+            tokio::spawn(async move { state.slot_changed("slot2").await })
+        };
+        let activation_waiter = {
+            let state = Arc::clone(&state);
+            tokio::spawn(async move { state.activated().await })
+        };
+        tokio::task::yield_now().await;
+
+        state.update_callback("slot1".to_string(), true);
+        state.update_callback("slot2".to_string(), true);
+
+        activation_waiter.await.unwrap();
+        assert_eq!(slot_waiter.await.unwrap(), Ok(true));
+        receiver.changed().await.unwrap();
+        assert!(*receiver.borrow());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_wait_leaves_state_usable() {
+        let state = async_with_slots();
+
+        let timed_out = tokio::time::timeout(Duration::from_millis(10), state.activated()).await;
+        assert!(timed_out.is_err());
+
+        state.update_callback("slot1".to_string(), true);
+        state.update_callback("slot2".to_string(), true);
+        state.activated().await;
+        assert_eq!(
+            state.slot_changed("unknown").await,
+            Err(XTStateError::UnknownSlot("unknown".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slot_changed_by_setup() {
+        let state = async_with_slots();
+        let waiter = |slot: &'static str| {
+            let state = Arc::clone(&state);
+            tokio::spawn(async move { state.slot_changed(slot).await })
+        };
+        let (changed, unchanged, dropped) = (waiter("slot2"), waiter("slot1"), waiter("slot1"));
+        tokio::task::yield_now().await;
+
+        state.setup(
+            SlotSetup::new()
+                .slots(["slot1".to_string()])
+                .values([("slot2".to_string(), true)])
+                .force(true),
+        );
+        assert_eq!(changed.await.unwrap(), Ok(true));
+        tokio::task::yield_now().await;
+        assert!(!unchanged.is_finished());
+
+        state.setup(SlotSetup::new().slots(["slot2".to_string()]).force(true));
+        assert_eq!(
+            dropped.await.unwrap(),
+            Err(XTStateError::UnknownSlot("slot1".to_string()))
+        );
+        unchanged.abort();
+    }
+
+    #[test]
+    fn test_concurrent_updates_publish_in_order() {
+        let state = Arc::new(AsyncXTState::new());
+        state.setup_slots(HashSet::from(["slot".to_string()]), false);
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        {
+            let changes = Arc::clone(&changes);
+            state.on_slot_change(move |change| changes.lock().unwrap().push(change.new));
+        }
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let state = Arc::clone(&state);
+                std::thread::spawn(move || {
+                    for i in 0..1_000 {
+                        state.update_callback("slot".to_string(), (i + thread) % 2 == 0);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(
+            *state.subscribe().borrow(),
+            state.read(|xt| xt.is_activated())
+        );
+        let changes = changes.lock().unwrap();
+        assert!(changes.windows(2).all(|pair| pair[0] != pair[1]));
+        assert_eq!(changes.last().copied(), state.read(|xt| xt.get("slot")));
+    }
+}
@@ -0,0 +1,316 @@
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicIsize, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crossbeam_queue::SegQueue;
+
+use crate::{Clock, HistoryEntry, HistoryEvent, Identifier, SystemClock, XTStateError};
+
+/// A lock-free alternative to `XTState` for slot sets that are fixed at construction.
+///
+/// Each slot's value lives in an atomic cell together with a count of its updates,
+/// activation is tracked with an atomic count of pending (false) slots, and history is
+/// appended to a lock-free queue, so `update_callback` only needs `&self` and never blocks.
+/// Activation follows `ActivationPolicy::All`.
+pub struct AtomicXTState<K = Identifier> {
+    index: HashMap<K, usize>,
+    names: Vec<Arc<K>>,
+    // The value in the lowest bit and the slot's version above it, changed by a single CAS
+    // so that every update knows exactly which value it replaced.
+    cells: Box<[AtomicU64]>,
+    // Signed because a racing 0 -> 1 transition may decrement before the matching
+    // 1 -> 0 transition increments; the count settles once both have landed. Until then it
+    // can read 0 while a slot is still false, so `is_activated` confirms a 0 against `cells`.
+    pending: AtomicIsize,
+    history: SegQueue<Record>,
+    next_seq: AtomicU64,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    position: usize,
+    previous: bool,
+    value: bool,
+    version: u64,
+    timestamp: i64,
+    seq: u64,
+    elapsed: Duration,
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug> AtomicXTState<K> {
+    pub fn new(slots: HashSet<K>) -> Result<Self, XTStateError<K>> {
+        AtomicXTState::with_clock(slots, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(slots: HashSet<K>, clock: Arc<dyn Clock>) -> Result<Self, XTStateError<K>> {
+        if slots.is_empty() {
+            return Err(XTStateError::NoSlots);
+        }
+        let names: Vec<Arc<K>> = slots.into_iter().map(Arc::new).collect();
+        let index = names
+            .iter()
+            .enumerate()
+            .map(|(position, name)| ((**name).clone(), position))
+            .collect();
+        let cells = names.iter().map(|_| AtomicU64::new(0)).collect();
+        Ok(AtomicXTState {
+            index,
+            pending: AtomicIsize::new(names.len() as isize),
+            names,
+            cells,
+            history: SegQueue::new(),
+            next_seq: AtomicU64::new(1),
+            clock,
+        })
+    }
+
+    pub fn is_activated(&self) -> bool {
+        self.pending.load(Ordering::Acquire) <= 0 && self.all_set()
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.load(Ordering::Acquire).max(0) as usize
+    }
+
+    pub fn get<Q>(&self, identifier: &Q) -> Option<bool>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.index
+            .get(identifier)
+            .map(|&position| self.bit(position))
+    }
+
+    pub fn slots(&self) -> impl Iterator<Item = (&K, bool)> {
+        self.names
+            .iter()
+            .enumerate()
+            .map(|(position, name)| (&**name, self.bit(position)))
+    }
+
+    pub fn pending_slots(&self) -> impl Iterator<Item = &K> {
+        self.slots()
+            .filter(|&(_, value)| !value)
+            .map(|(identifier, _)| identifier)
+    }
+
+    pub fn update_callback<Q>(&self, identifier: &Q, value: bool)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if let Err(err) = self.try_update(identifier, value) {
+            panic!("{}", err);
+        }
+    }
+
+    pub fn try_update<Q>(&self, identifier: &Q, value: bool) -> Result<(), XTStateError<K>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let Some(&position) = self.index.get(identifier) else {
+            return Err(XTStateError::UnknownSlot(identifier.to_owned()));
+        };
+
+        let cell = &self.cells[position];
+        let mut current = cell.load(Ordering::Acquire);
+        let version = loop {
+            let version = (current >> 1) + 1;
+            match cell.compare_exchange_weak(
+                current,
+                version << 1 | value as u64,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break version,
+                Err(actual) => current = actual,
+            }
+        };
+        let previous = current & 1 != 0;
+        match (previous, value) {
+            (false, true) => {
+                self.pending.fetch_sub(1, Ordering::AcqRel);
+            }
+            (true, false) => {
+                self.pending.fetch_add(1, Ordering::AcqRel);
+            }
+            _ => {}
+        }
+
+        self.history.push(Record {
+            position,
+            previous,
+            value,
+            version,
+            timestamp: self.clock.now_millis(),
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            elapsed: self.clock.elapsed(),
+        });
+        Ok(())
+    }
+
+    /// Removes and returns the history recorded so far, ordered by `seq`. Each slot's entries
+    /// follow the order its updates took effect, so their `previous` values chain up. Entries
+    /// from updates racing with the drain may land in the next call, possibly with a lower
+    /// `seq`.
+    pub fn drain_history(&self) -> Vec<HistoryEntry<K>> {
+        let mut records: Vec<_> = std::iter::from_fn(|| self.history.pop()).collect();
+        records.sort_unstable_by_key(|record| record.seq);
+
+        // Racing updates of one slot may draw their `seq` in the opposite order to their CAS,
+        // so hand each slot's sequence numbers back out in version order.
+        let mut per_slot: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, record) in records.iter().enumerate() {
+            per_slot.entry(record.position).or_default().push(index);
+        }
+        for indices in per_slot.values() {
+            let mut slot_records: Vec<_> = indices.iter().map(|&index| records[index]).collect();
+            slot_records.sort_unstable_by_key(|record| record.version);
+            for (&index, record) in indices.iter().zip(slot_records) {
+                records[index] = Record {
+                    seq: records[index].seq,
+                    ..record
+                };
+            }
+        }
+
+        records
+            .into_iter()
+            .map(|record| HistoryEntry {
+                slot: Arc::clone(&self.names[record.position]),
+                event: HistoryEvent::Updated(record.value),
+                previous: Some(record.previous),
+                timestamp: record.timestamp,
+                seq: record.seq,
+                elapsed: record.elapsed,
+            })
+            .collect()
+    }
+
+    fn all_set(&self) -> bool {
+        (0..self.cells.len()).all(|position| self.bit(position))
+    }
+
+    fn bit(&self, position: usize) -> bool {
+        self.cells[position].load(Ordering::Acquire) & 1 != 0
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug> fmt::Debug for AtomicXTState<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtomicXTState")
+            .field("slots", &self.slots().collect::<HashMap<_, _>>())
+            .field("pending", &self.pending_count())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XTState;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_updates_activate() {
+        let slots: HashSet<Identifier> = (0..130).map(|i| format!("worker{}", i)).collect();
+        let state = Arc::new(AtomicXTState::new(slots).unwrap());
+
+        let handles: Vec<_> = (0..10)
+            .map(|thread| {
+                let state = Arc::clone(&state);
+                thread::spawn(move || {
+                    for i in (thread..130).step_by(10) {
+                        state.update_callback(&format!("worker{}", i), false);
+                        state.update_callback(&format!("worker{}", i), true);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert!(state.is_activated());
+        assert_eq!(state.pending_slots().count(), 0);
+        assert_eq!(state.drain_history().len(), 260);
+        assert!(state.drain_history().is_empty());
+
+        state.update_callback("worker64", false);
+        assert!(!state.is_activated());
+        assert_eq!(state.get("worker64"), Some(false));
+        assert_eq!(state.pending_slots().collect::<Vec<_>>(), vec!["worker64"]);
+    }
+
+    #[test]
+    fn test_racing_updates_never_activate_early() {
+        let state = Arc::new(
+            AtomicXTState::new(HashSet::from(["x".to_string(), "y".to_string()])).unwrap(),
+        );
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        // "y" stays false throughout, so the state must never report activation, even while
+        // the pending count is skewed by two updates racing on "x".
+        let writers: Vec<_> = [true, false, true, false]
+            .into_iter()
+            .map(|value| {
+                let state = Arc::clone(&state);
+                thread::spawn(move || {
+                    for _ in 0..100_000 {
+                        state.update_callback("x", value);
+                    }
+                })
+            })
+            .collect();
+        let reader = {
+            let (state, stop) = (Arc::clone(&state), Arc::clone(&stop));
+            thread::spawn(move || {
+                let mut activations = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    activations += state.is_activated() as usize;
+                }
+                activations
+            })
+        };
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        stop.store(true, Ordering::Relaxed);
+        assert_eq!(reader.join().unwrap(), 0);
+        assert!(!state.is_activated());
+
+        let history = state.drain_history();
+        assert_eq!(history.len(), 400_000);
+        assert_eq!(history[0].previous, Some(false));
+        for pair in history.windows(2) {
+            assert!(pair[0].seq < pair[1].seq);
+            assert_eq!(pair[1].previous, pair[0].value());
+        }
+        assert_eq!(history.last().unwrap().value(), state.get("x"));
+    }
+
+    #[test]
+    fn test_errors_match_xtstate() {
+        assert!(matches!(
+            AtomicXTState::new(HashSet::<Identifier>::new()),
+            Err(XTStateError::NoSlots)
+        ));
+        assert_eq!(
+            XTState::<Identifier>::new().try_setup_slots(HashSet::new(), false),
+            Err(XTStateError::NoSlots)
+        );
+
+        let state = AtomicXTState::new(HashSet::from(["slot1".to_string()])).unwrap();
+        assert_eq!(
+            state.try_update("unknown", true),
+            Err(XTStateError::UnknownSlot("unknown".to_string()))
+        );
+        assert!(state.drain_history().is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Source of the millisecond timestamps recorded in history entries and slot changes.
+pub trait Clock: Send + Sync {
+    fn now_millis(&self) -> i64;
+
+    /// Monotonic time since the clock's origin, unaffected by wall-clock adjustments. Defaults
+    /// to an `Instant` taken the first time any clock is asked in this process.
+    fn elapsed(&self) -> Duration {
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed()
+    }
+}
+
+/// Wall-clock time via `chrono::Utc::now()`, the default for every `XTState`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests and replay.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    millis: AtomicI64,
+}
+
+impl ManualClock {
+    pub fn new(millis: i64) -> Self {
+        ManualClock {
+            millis: AtomicI64::new(millis),
+        }
+    }
+
+    pub fn set(&self, millis: i64) {
+        self.millis.store(millis, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.millis
+            .fetch_add(by.as_millis() as i64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> i64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+
+    /// The current manual time, clamped at zero.
+    fn elapsed(&self) -> Duration {
+        Duration::from_millis(self.now_millis().max(0) as u64)
+    }
+}
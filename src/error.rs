@@ -0,0 +1,78 @@
+use std::fmt;
+
+use crate::Identifier;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XTStateError<K = Identifier> {
+    NotSetUp,
+    AlreadySetUp,
+    UnknownSlot(K),
+    SlotExists(K),
+    SlotInUse(K),
+    StaleHandle,
+    NoSlots,
+    InvalidSnapshot(String),
+    InvalidPolicy(String),
+    InvalidHistory(String),
+}
+
+impl<K: fmt::Debug> fmt::Display for XTStateError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XTStateError::NotSetUp => write!(f, "xtstate is not set up. call setup_slots first."),
+            XTStateError::AlreadySetUp => {
+                write!(f, "xtstate is already set up. use force to override.")
+            }
+            XTStateError::UnknownSlot(identifier) => {
+                write!(
+                    f,
+                    "identifier {} is not defined in the slots.",
+                    Quoted(identifier)
+                )
+            }
+            XTStateError::SlotExists(identifier) => {
+                write!(
+                    f,
+                    "identifier {} is already defined in the slots.",
+                    Quoted(identifier)
+                )
+            }
+            XTStateError::SlotInUse(identifier) => write!(
+                f,
+                "identifier {} is referenced by the activation policy.",
+                Quoted(identifier)
+            ),
+            XTStateError::StaleHandle => write!(
+                f,
+                "slot handle is stale. the slot was removed or the state was set up again."
+            ),
+            XTStateError::NoSlots => {
+                write!(
+                    f,
+                    "no slots are defined. call setup_slots with valid slots."
+                )
+            }
+            XTStateError::InvalidSnapshot(reason) => write!(f, "invalid snapshot: {}.", reason),
+            XTStateError::InvalidPolicy(reason) => {
+                write!(f, "invalid activation policy: {}.", reason)
+            }
+            XTStateError::InvalidHistory(reason) => write!(f, "invalid history: {}.", reason),
+        }
+    }
+}
+
+/// Renders an identifier as `'name'`, dropping the quotes `Debug` puts around string keys.
+struct Quoted<'a, K>(&'a K);
+
+impl<K: fmt::Debug> fmt::Display for Quoted<'_, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let debug = format!("{:?}", self.0);
+        let name = debug
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+            .unwrap_or(&debug);
+        write!(f, "'{}'", name)
+    }
+}
+
+impl<K: fmt::Debug> std::error::Error for XTStateError<K> {}
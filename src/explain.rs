@@ -0,0 +1,102 @@
+use std::fmt;
+use std::hash::Hash;
+
+use crate::{ActivationState, Identifier, XTState};
+
+/// A slot that does not currently count towards activation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch<K = Identifier, V = bool> {
+    pub slot: K,
+    pub value: V,
+    /// The value the slot must hold, if it was set up with a `SlotSpec`.
+    pub target: Option<V>,
+    /// `false` for slots the activation policy can do without.
+    pub required: bool,
+}
+
+/// Why a state is or is not activated, as returned by `XTState::explain`. Displays as e.g.
+/// `waiting: "maintenance_mode" is true (expected false)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation<K = Identifier, V = bool> {
+    pub state: ActivationState,
+    pub mismatched: Vec<Mismatch<K, V>>,
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Display for Explanation<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.state {
+            ActivationState::Activated => write!(f, "activated")?,
+            ActivationState::Waiting => write!(f, "waiting")?,
+            ActivationState::Failed => write!(f, "failed")?,
+        }
+        for (i, mismatch) in self.mismatched.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { ", " };
+            write!(
+                f,
+                "{}{:?} is {:?}",
+                separator, mismatch.slot, mismatch.value
+            )?;
+            if let Some(target) = &mismatch.target {
+                write!(f, " (expected {:?})", target)?;
+            }
+            if !mismatch.required {
+                write!(f, " (optional)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: Clone + PartialEq + fmt::Debug> XTState<K, V> {
+    /// The activation state along with every slot that is not satisfied.
+    pub fn explain(&self) -> Explanation<K, V> {
+        let mismatched = self
+            .slots
+            .iter()
+            .filter(|slot| !slot.satisfied)
+            .map(|slot| Mismatch {
+                slot: (*slot.name).clone(),
+                value: slot.value.clone(),
+                target: slot.target.clone(),
+                required: slot.required,
+            })
+            .collect();
+        Explanation {
+            state: self.state(),
+            mismatched,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SlotSetup, SlotSpec};
+
+    #[test]
+    fn test_targets_and_explain() {
+        let mut xt_state = XTState::new();
+        xt_state.setup(SlotSetup::new().specs([
+            ("maintenance_mode".to_string(), SlotSpec::new(true, false)),
+            ("db".to_string(), SlotSpec::new(true, true)),
+        ]));
+        assert!(!xt_state.is_activated());
+        assert_eq!(
+            xt_state.explain().to_string(),
+            "waiting: \"maintenance_mode\" is true (expected false)"
+        );
+
+        xt_state.update_callback("maintenance_mode".to_string(), false);
+        assert!(xt_state.is_activated());
+        assert_eq!(xt_state.explain().to_string(), "activated");
+
+        xt_state.update_callback("db".to_string(), false);
+        let explanation = xt_state.explain();
+        assert_eq!(explanation.mismatched.len(), 1);
+        assert_eq!(explanation.mismatched[0].slot, "db");
+        assert_eq!(explanation.mismatched[0].target, Some(true));
+
+        let restored = XTState::restore(xt_state.snapshot()).unwrap();
+        assert_eq!(restored.explain(), explanation);
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::Identifier;
+
+/// A boolean condition over slot names, e.g. `db && (cache || fallback_cache) && !maintenance`.
+///
+/// `!` binds tighter than `&&`, which binds tighter than `||`. Slot names may contain
+/// ASCII letters, digits, `_`, `-`, `.` and `:`; `true` and `false` are literals.
+/// Parsed expressions hold `String` names; use `try_map` to convert them to another key type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Expr<K = Identifier> {
+    Const(bool),
+    Slot(K),
+    Not(Box<Expr<K>>),
+    And(Box<Expr<K>>, Box<Expr<K>>),
+    Or(Box<Expr<K>>, Box<Expr<K>>),
+}
+
+/// A parse failure; `position` is the byte offset into the source where it was detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, ParseError> {
+        let mut parser = Parser {
+            source,
+            position: 0,
+            depth: 0,
+        };
+        let expr = parser.or()?;
+        parser.skip_whitespace();
+        if parser.position < source.len() {
+            return Err(parser.error("unexpected trailing input"));
+        }
+        Ok(expr)
+    }
+}
+
+impl<K> Expr<K> {
+    pub fn evaluate(&self, value_of: &impl Fn(&K) -> bool) -> bool {
+        match self {
+            Expr::Const(value) => *value,
+            Expr::Slot(slot) => value_of(slot),
+            Expr::Not(inner) => !inner.evaluate(value_of),
+            Expr::And(left, right) => left.evaluate(value_of) && right.evaluate(value_of),
+            Expr::Or(left, right) => left.evaluate(value_of) || right.evaluate(value_of),
+        }
+    }
+
+    /// Three-valued evaluation: `None` marks a slot whose value is not known yet, and the
+    /// result is `None` unless the known slots alone decide the expression.
+    pub fn evaluate_partial(&self, value_of: &impl Fn(&K) -> Option<bool>) -> Option<bool> {
+        match self {
+            Expr::Const(value) => Some(*value),
+            Expr::Slot(slot) => value_of(slot),
+            Expr::Not(inner) => inner.evaluate_partial(value_of).map(|value| !value),
+            Expr::And(left, right) => {
+                match (
+                    left.evaluate_partial(value_of),
+                    right.evaluate_partial(value_of),
+                ) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }
+            }
+            Expr::Or(left, right) => {
+                match (
+                    left.evaluate_partial(value_of),
+                    right.evaluate_partial(value_of),
+                ) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    pub fn identifiers(&self) -> HashSet<&K>
+    where
+        K: Eq + Hash,
+    {
+        let mut identifiers = HashSet::new();
+        self.collect_identifiers(&mut identifiers);
+        identifiers
+    }
+
+    /// Converts every slot name, failing on the first one `f` rejects.
+    pub fn try_map<L, E>(self, f: &mut impl FnMut(K) -> Result<L, E>) -> Result<Expr<L>, E> {
+        Ok(match self {
+            Expr::Const(value) => Expr::Const(value),
+            Expr::Slot(slot) => Expr::Slot(f(slot)?),
+            Expr::Not(inner) => Expr::Not(Box::new(inner.try_map(f)?)),
+            Expr::And(left, right) => {
+                Expr::And(Box::new(left.try_map(f)?), Box::new(right.try_map(f)?))
+            }
+            Expr::Or(left, right) => {
+                Expr::Or(Box::new(left.try_map(f)?), Box::new(right.try_map(f)?))
+            }
+        })
+    }
+
+    fn collect_identifiers<'a>(&'a self, identifiers: &mut HashSet<&'a K>)
+    where
+        K: Eq + Hash,
+    {
+        match self {
+            Expr::Const(_) => {}
+            Expr::Slot(slot) => {
+                identifiers.insert(slot);
+            }
+            Expr::Not(inner) => inner.collect_identifiers(identifiers),
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.collect_identifiers(identifiers);
+                right.collect_identifiers(identifiers);
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Expr::parse(source)
+    }
+}
+
+impl<K: fmt::Display> fmt::Display for Expr<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Slot(slot) => write!(f, "{}", slot),
+            Expr::Not(inner) => write!(f, "!{}", Parenthesized(inner)),
+            Expr::And(left, right) => {
+                write!(f, "{} && {}", Parenthesized(left), Parenthesized(right))
+            }
+            Expr::Or(left, right) => write!(f, "{} || {}", left, right),
+        }
+    }
+}
+
+struct Parenthesized<'a, K>(&'a Expr<K>);
+
+impl<K: fmt::Display> fmt::Display for Parenthesized<'_, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Expr::And(..) | Expr::Or(..) => write!(f, "({})", self.0),
+            _ => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// How deeply `!`, parentheses and chained `&&`/`||` may nest before parsing gives up, so
+/// that evaluating and dropping the parsed tree cannot overflow the stack.
+const MAX_DEPTH: usize = 256;
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    // Every operator in a chain nests the tree one level deeper.
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let depth = self.depth;
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            self.nest()?;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let depth = self.depth;
+        let mut expr = self.unary()?;
+        while self.eat("&&") {
+            self.nest()?;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat("!") {
+            self.nest()?;
+            let inner = self.unary()?;
+            self.depth -= 1;
+            return Ok(Expr::Not(Box::new(inner)));
+        }
+        if self.eat("(") {
+            self.nest()?;
+            let expr = self.or()?;
+            self.depth -= 1;
+            if !self.eat(")") {
+                return Err(self.error("expected ')'"));
+            }
+            return Ok(expr);
+        }
+        self.identifier()
+    }
+
+    fn nest(&mut self) -> Result<(), ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("expression nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> Result<Expr, ParseError> {
+        self.skip_whitespace();
+        let rest = &self.source[self.position..];
+        let length = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')))
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return Err(match rest.chars().next() {
+                Some(c) => self.error(&format!("unexpected '{}'", c)),
+                None => self.error("unexpected end of input"),
+            });
+        }
+        self.position += length;
+        Ok(match &rest[..length] {
+            "true" => Expr::Const(true),
+            "false" => Expr::Const(false),
+            name => Expr::Slot(name.to_string()),
+        })
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.source[self.position..].starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.source[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            position: self.position,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_evaluate() {
+        let expr = Expr::parse("db && (cache || fallback_cache) && !maintenance").unwrap();
+        let ready = |up: &[&str]| expr.evaluate(&|slot: &Identifier| up.contains(&slot.as_str()));
+
+        assert!(ready(&["db", "cache"]));
+        assert!(ready(&["db", "fallback_cache"]));
+        assert!(!ready(&["db"]));
+        assert!(!ready(&["db", "cache", "maintenance"]));
+
+        let identifiers = expr.identifiers();
+        assert_eq!(identifiers.len(), 4);
+        assert!(identifiers.contains(&"fallback_cache".to_string()));
+        assert_eq!(Expr::parse(&expr.to_string()).unwrap(), expr);
+    }
+
+    #[test]
+    fn test_try_map_slot_names() {
+        let expr = Expr::parse("a && !b").unwrap();
+        let mapped = expr
+            .clone()
+            .try_map(&mut |name| match name.as_str() {
+                "a" => Ok(1u8),
+                "b" => Ok(2u8),
+                _ => Err(name),
+            })
+            .unwrap();
+        assert!(mapped.evaluate(&|&slot| slot == 1));
+
+        let unknown = Expr::parse("a && c")
+            .unwrap()
+            .try_map(&mut |name| match name.as_str() {
+                "a" => Ok(1u8),
+                _ => Err(name),
+            });
+        assert_eq!(unknown, Err("c".to_string()));
+    }
+
+    #[test]
+    fn test_parse_errors_report_positions() {
+        assert_eq!(
+            Expr::parse("db && (cache"),
+            Err(ParseError {
+                position: 12,
+                message: "expected ')'".to_string()
+            })
+        );
+        assert_eq!(Expr::parse("db &&").unwrap_err().position, 5);
+        assert_eq!(Expr::parse("db cache").unwrap_err().position, 3);
+        assert_eq!(Expr::parse("db & cache").unwrap_err().position, 3);
+    }
+
+    #[test]
+    fn test_parse_rejects_deep_nesting() {
+        let nested = format!("{}a{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(Expr::parse(&nested).is_ok());
+
+        let error = Expr::parse(&format!("{}a", "!".repeat(200_000))).unwrap_err();
+        assert_eq!(error.position, MAX_DEPTH + 1);
+        assert_eq!(error.message, "expression nested too deeply");
+        let error = Expr::parse(&format!("{}a", "(".repeat(200_000))).unwrap_err();
+        assert_eq!(error.position, MAX_DEPTH + 1);
+
+        let chain = |terms: usize, operator: &str| vec!["a"; terms].join(operator);
+        assert!(Expr::parse(&chain(MAX_DEPTH + 1, " && ")).is_ok());
+        let error = Expr::parse(&chain(200_000, " || ")).unwrap_err();
+        assert_eq!(error.message, "expression nested too deeply");
+        assert_eq!(error.position, (MAX_DEPTH + 1) * 5 - 1);
+    }
+}
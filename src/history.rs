@@ -0,0 +1,698 @@
+use std::borrow::Borrow;
+use std::collections::{HashMap, VecDeque, vec_deque};
+use std::fmt;
+use std::hash::Hash;
+use std::ops::Index;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::Identifier;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HistoryEvent<V = bool> {
+    Updated(V),
+    Added(V),
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HistoryEntry<K = Identifier, V = bool> {
+    pub slot: Arc<K>,
+    pub event: HistoryEvent<V>,
+    /// The slot's value before this entry; `None` for additions.
+    #[cfg_attr(feature = "serde", serde(default = "none"))]
+    pub previous: Option<V>,
+    /// Wall-clock milliseconds; may collide or go backwards.
+    pub timestamp: i64,
+    /// Strictly increasing per state, starting at 1 and never reused across setups.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seq: u64,
+    /// Monotonic `Clock::elapsed` at the time of the entry.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub elapsed: Duration,
+}
+
+#[cfg(feature = "serde")]
+fn none<V>() -> Option<V> {
+    None
+}
+
+impl<K, V: Clone> HistoryEntry<K, V> {
+    /// The slot's value after this entry, or `None` if the slot was removed.
+    pub fn value(&self) -> Option<V> {
+        match &self.event {
+            HistoryEvent::Updated(value) | HistoryEvent::Added(value) => Some(value.clone()),
+            HistoryEvent::Removed => None,
+        }
+    }
+}
+
+/// Limits on how much history is kept. Every limit defaults to `None`, i.e. unbounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Retention {
+    /// Keep at most this many entries, dropping the oldest first.
+    pub max_entries: Option<usize>,
+    /// Drop entries older than this, measured against the newest entry's timestamp.
+    pub max_age: Option<Duration>,
+    /// Keep at most this many entries per slot, dropping that slot's oldest first.
+    pub max_per_slot: Option<usize>,
+}
+
+/// How many entries each retention limit has dropped so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Evictions {
+    pub max_entries: u64,
+    pub max_age: u64,
+    pub max_per_slot: u64,
+}
+
+impl Evictions {
+    pub fn total(&self) -> u64 {
+        self.max_entries + self.max_age + self.max_per_slot
+    }
+}
+
+/// The recorded history of an `XTState`, oldest entry first, bounded by a `Retention`.
+///
+/// Evicted entries are folded into a base slot map, so past states can still be reconstructed
+/// for any instant after the newest eviction.
+#[derive(Clone)]
+pub struct History<K = Identifier, V = bool> {
+    // Entries evicted by `max_per_slot` stay in place, marked, until they outnumber the live
+    // ones, so evicting from the middle is amortised O(1). The front entry is always live.
+    entries: VecDeque<Retained<K, V>>,
+    live: usize,
+    // Positions of each slot's live entries, oldest first, counting the `popped` entries
+    // before the front. Only maintained while `retention.max_per_slot` is set.
+    per_slot: HashMap<Arc<K>, VecDeque<usize>>,
+    popped: usize,
+    retention: Retention,
+    evictions: Evictions,
+    // Slot values just before the oldest retained entry, valid from `horizon` onwards.
+    base: HashMap<K, V>,
+    horizon: Option<i64>,
+    next_seq: u64,
+    run: u64,
+    started_at: Option<i64>,
+}
+
+#[derive(Clone)]
+struct Retained<K, V> {
+    entry: HistoryEntry<K, V>,
+    evicted: bool,
+}
+
+/// The entries of a `History`, oldest first.
+pub struct HistoryIter<'a, K = Identifier, V = bool> {
+    entries: vec_deque::Iter<'a, Retained<K, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for HistoryIter<'a, K, V> {
+    type Item = &'a HistoryEntry<K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let retained = self.entries.find(|retained| !retained.evicted)?;
+        self.remaining -= 1;
+        Some(&retained.entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for HistoryIter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let retained = self.entries.rfind(|retained| !retained.evicted)?;
+        self.remaining -= 1;
+        Some(&retained.entry)
+    }
+}
+
+impl<K, V> ExactSizeIterator for HistoryIter<'_, K, V> {}
+
+impl<K, V> History<K, V> {
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    /// Constant time unless evicted entries are awaiting compaction.
+    pub fn get(&self, index: usize) -> Option<&HistoryEntry<K, V>> {
+        if self.live == self.entries.len() {
+            self.entries.get(index).map(|retained| &retained.entry)
+        } else {
+            self.iter().nth(index)
+        }
+    }
+
+    pub fn iter(&self) -> HistoryIter<'_, K, V> {
+        HistoryIter {
+            entries: self.entries.iter(),
+            remaining: self.live,
+        }
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    pub fn evictions(&self) -> Evictions {
+        self.evictions
+    }
+
+    /// The run this history belongs to. Every setup starts a new run; 0 means not set up yet.
+    pub fn run(&self) -> u64 {
+        self.run
+    }
+
+    /// When the run was set up, in milliseconds.
+    pub fn started_at(&self) -> Option<i64> {
+        self.started_at
+    }
+
+    /// Whether any entry has been evicted, i.e. the history no longer starts at setup.
+    pub fn is_truncated(&self) -> bool {
+        self.evictions.total() > 0
+    }
+
+    /// The earliest timestamp `state_at` can answer for: the setup time, or the timestamp of
+    /// the newest evicted entry. `None` before setup.
+    pub fn horizon(&self) -> Option<i64> {
+        self.horizon
+    }
+
+    /// Entries recorded after the one numbered `seq`, for consumers tailing the history: pass
+    /// the `seq` of the last entry seen. A gap before the first returned `seq` means entries
+    /// were evicted in between.
+    pub fn since(&self, seq: u64) -> impl DoubleEndedIterator<Item = &HistoryEntry<K, V>> {
+        let start = self
+            .entries
+            .partition_point(|retained| retained.entry.seq <= seq);
+        self.entries
+            .range(start..)
+            .filter(|retained| !retained.evicted)
+            .map(|retained| &retained.entry)
+    }
+
+    /// Entries recorded in `from..to` (milliseconds, end exclusive).
+    pub fn between(
+        &self,
+        from: i64,
+        to: i64,
+    ) -> impl DoubleEndedIterator<Item = &HistoryEntry<K, V>> {
+        self.iter()
+            .filter(move |entry| (from..to).contains(&entry.timestamp))
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone + PartialEq> History<K, V> {
+    pub fn for_slot<'a, Q>(
+        &'a self,
+        slot: &Q,
+    ) -> impl DoubleEndedIterator<Item = &'a HistoryEntry<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.iter()
+            .filter(move |entry| (*entry.slot).borrow() == slot)
+    }
+
+    /// The most recent entry that actually changed the slot: an addition, a removal, or an
+    /// update to a different value. Updates that rewrote the same value are skipped.
+    pub fn last_change<Q>(&self, slot: &Q) -> Option<&HistoryEntry<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut previous = self.base.get(slot).cloned();
+        let mut last = None;
+        for entry in self.for_slot(slot) {
+            let value = entry.value();
+            if value != previous || !matches!(entry.event, HistoryEvent::Updated(_)) {
+                last = Some(entry);
+            }
+            previous = value;
+        }
+        last
+    }
+
+    /// Reconstructs the slot map as it was at `timestamp`, or `None` if that instant is
+    /// before `horizon()`.
+    pub fn state_at(&self, timestamp: i64) -> Option<HashMap<K, V>> {
+        if self.horizon.is_none_or(|horizon| timestamp < horizon) {
+            return None;
+        }
+        let mut slots: HashMap<&K, V> = self
+            .base
+            .iter()
+            .map(|(slot, value)| (slot, value.clone()))
+            .collect();
+        for entry in self.iter().take_while(|entry| entry.timestamp <= timestamp) {
+            match entry.value() {
+                Some(value) => slots.insert(&entry.slot, value),
+                None => slots.remove(&*entry.slot),
+            };
+        }
+        Some(
+            slots
+                .into_iter()
+                .map(|(slot, value)| (slot.clone(), value))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn new(retention: Retention) -> Self {
+        History {
+            entries: VecDeque::new(),
+            live: 0,
+            per_slot: HashMap::new(),
+            popped: 0,
+            retention,
+            evictions: Evictions::default(),
+            base: HashMap::new(),
+            horizon: None,
+            next_seq: 1,
+            run: 0,
+            started_at: None,
+        }
+    }
+
+    pub(crate) fn restore(
+        entries: Vec<HistoryEntry<K, V>>,
+        retention: Retention,
+        evictions: Evictions,
+        base: HashMap<K, V>,
+        horizon: Option<i64>,
+        next_seq: u64,
+    ) -> Self {
+        let mut history = History::new(Retention::default());
+        let after_last = entries.last().map_or(1, |entry| entry.seq + 1);
+        history.live = entries.len();
+        history.entries = entries
+            .into_iter()
+            .map(|entry| Retained {
+                entry,
+                evicted: false,
+            })
+            .collect();
+        history.evictions = evictions;
+        history.base = base;
+        history.horizon = horizon;
+        history.next_seq = next_seq.max(after_last);
+        history.set_retention(retention);
+        history
+    }
+
+    pub(crate) fn with_run(mut self, run: u64, started_at: Option<i64>) -> Self {
+        self.run = run;
+        self.started_at = started_at;
+        self
+    }
+
+    /// Starts the next run from the given initial slot values and returns the finished one.
+    /// Retention and sequence numbering carry over.
+    pub(crate) fn start_run(
+        &mut self,
+        slots: impl IntoIterator<Item = (K, V)>,
+        timestamp: i64,
+    ) -> History<K, V> {
+        let mut next = History::new(self.retention);
+        next.next_seq = self.next_seq;
+        next.run = self.run + 1;
+        next.started_at = Some(timestamp);
+        next.base = slots.into_iter().collect();
+        next.horizon = Some(timestamp);
+        std::mem::replace(self, next)
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        slot: Arc<K>,
+        event: HistoryEvent<V>,
+        previous: Option<V>,
+        timestamp: i64,
+        elapsed: Duration,
+    ) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.push(HistoryEntry {
+            slot,
+            event,
+            previous,
+            timestamp,
+            seq,
+            elapsed,
+        });
+    }
+
+    pub(crate) fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Makes the next recorded entry use `seq`, which must not go backwards.
+    pub(crate) fn skip_to(&mut self, seq: u64) {
+        debug_assert!(seq >= self.next_seq);
+        self.next_seq = seq;
+    }
+
+    fn push(&mut self, entry: HistoryEntry<K, V>) {
+        let now = entry.timestamp;
+        let slot = Arc::clone(&entry.slot);
+        self.entries.push_back(Retained {
+            entry,
+            evicted: false,
+        });
+        self.live += 1;
+        if let Some(max_per_slot) = self.retention.max_per_slot {
+            let positions = self.per_slot.entry(Arc::clone(&slot)).or_default();
+            positions.push_back(self.popped + self.entries.len() - 1);
+            if positions.len() > max_per_slot {
+                let oldest = positions.pop_front().expect("the new entry is present");
+                if positions.is_empty() {
+                    self.per_slot.remove(&slot);
+                }
+                self.evict(oldest - self.popped);
+                self.evictions.max_per_slot += 1;
+                self.trim_front();
+                if self.entries.len() > 2 * self.live {
+                    self.compact();
+                }
+            }
+        }
+        self.prune(now);
+    }
+
+    /// Applies `max_age` relative to `now` and `max_entries`.
+    pub(crate) fn prune(&mut self, now: i64) {
+        if let Some(max_age) = self.retention.max_age {
+            let max_age = i64::try_from(max_age.as_millis()).unwrap_or(i64::MAX);
+            let cutoff = now.saturating_sub(max_age);
+            while self
+                .entries
+                .front()
+                .is_some_and(|oldest| oldest.entry.timestamp < cutoff)
+            {
+                self.pop_front();
+                self.evictions.max_age += 1;
+            }
+        }
+        if let Some(max_entries) = self.retention.max_entries {
+            while self.live > max_entries {
+                self.pop_front();
+                self.evictions.max_entries += 1;
+            }
+        }
+    }
+
+    /// Replaces the limits and immediately evicts whatever no longer fits.
+    pub(crate) fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+        if let Some(max_per_slot) = retention.max_per_slot {
+            // Count newest to oldest so the most recent entries of each slot survive, then
+            // evict oldest first so the base map folds in chronological order.
+            let mut counts = HashMap::new();
+            let keep: Vec<bool> = self
+                .entries
+                .iter()
+                .rev()
+                .map(|retained| {
+                    let count = counts.entry(Arc::clone(&retained.entry.slot)).or_insert(0);
+                    *count += usize::from(!retained.evicted);
+                    retained.evicted || *count <= max_per_slot
+                })
+                .collect();
+            for (index, keep) in keep.into_iter().rev().enumerate() {
+                if !keep {
+                    self.evict(index);
+                    self.evictions.max_per_slot += 1;
+                }
+            }
+        }
+        self.compact();
+        if let Some(newest) = self.iter().next_back().map(|entry| entry.timestamp) {
+            self.prune(newest);
+        }
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<HistoryEntry<K, V>> {
+        self.iter().cloned().collect()
+    }
+
+    pub(crate) fn base(&self) -> &HashMap<K, V> {
+        &self.base
+    }
+
+    fn pop_front(&mut self) {
+        let Some(Retained { entry, .. }) = self.entries.pop_front() else {
+            return;
+        };
+        self.popped += 1;
+        self.live -= 1;
+        if let Some(positions) = self.per_slot.get_mut(&entry.slot) {
+            positions.pop_front();
+            if positions.is_empty() {
+                self.per_slot.remove(&entry.slot);
+            }
+        }
+        self.fold(&entry);
+        self.trim_front();
+    }
+
+    /// Marks the entry at `index` as evicted and folds it into the base.
+    fn evict(&mut self, index: usize) {
+        let retained = &mut self.entries[index];
+        retained.evicted = true;
+        let entry = retained.entry.clone();
+        self.live -= 1;
+        self.fold(&entry);
+    }
+
+    fn trim_front(&mut self) {
+        while self
+            .entries
+            .front()
+            .is_some_and(|retained| retained.evicted)
+        {
+            self.entries.pop_front();
+            self.popped += 1;
+        }
+    }
+
+    /// Drops evicted entries and renumbers the per-slot positions.
+    fn compact(&mut self) {
+        self.entries.retain(|retained| !retained.evicted);
+        self.popped = 0;
+        self.per_slot.clear();
+        if self.retention.max_per_slot.is_some() {
+            for (position, retained) in self.entries.iter().enumerate() {
+                self.per_slot
+                    .entry(Arc::clone(&retained.entry.slot))
+                    .or_default()
+                    .push_back(position);
+            }
+        }
+    }
+
+    fn fold(&mut self, evicted: &HistoryEntry<K, V>) {
+        self.horizon = Some(
+            self.horizon
+                .map_or(evicted.timestamp, |horizon| horizon.max(evicted.timestamp)),
+        );
+        match evicted.value() {
+            Some(value) => self.base.insert((*evicted.slot).clone(), value),
+            None => self.base.remove(&*evicted.slot),
+        };
+    }
+}
+
+impl<K, V> Index<usize> for History<K, V> {
+    type Output = HistoryEntry<K, V>;
+
+    fn index(&self, index: usize) -> &HistoryEntry<K, V> {
+        self.get(index).expect("history index out of bounds")
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a History<K, V> {
+    type Item = &'a HistoryEntry<K, V>;
+    type IntoIter = HistoryIter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: PartialEq, V: PartialEq> PartialEq for History<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+            && self.retention == other.retention
+            && self.evictions == other.evictions
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for History<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("History")
+            .field("entries", &self.iter().collect::<Vec<_>>())
+            .field("evictions", &self.evictions)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(history: &mut History, slot: &str, value: bool, timestamp: i64) {
+        let elapsed = Duration::from_millis(timestamp as u64);
+        history.record(
+            Arc::new(slot.to_string()),
+            HistoryEvent::Updated(value),
+            Some(!value),
+            timestamp,
+            elapsed,
+        );
+    }
+
+    fn slots(history: &History) -> Vec<&str> {
+        history.iter().map(|entry| entry.slot.as_str()).collect()
+    }
+
+    #[test]
+    fn test_retention_limits() {
+        let mut history = History::new(Retention {
+            max_entries: Some(4),
+            max_per_slot: Some(2),
+            ..Retention::default()
+        });
+        for (i, slot) in ["a", "b", "a", "a", "c", "a", "d"].into_iter().enumerate() {
+            record(&mut history, slot, i % 2 == 0, i as i64);
+        }
+        assert_eq!(slots(&history), ["a", "c", "a", "d"]);
+        assert_eq!(
+            history.evictions(),
+            Evictions {
+                max_entries: 1,
+                max_age: 0,
+                max_per_slot: 2,
+            }
+        );
+        assert!(history.is_truncated());
+
+        let mut history = History::new(Retention {
+            max_age: Some(Duration::from_millis(100)),
+            ..Retention::default()
+        });
+        record(&mut history, "a", true, 0);
+        record(&mut history, "b", true, 50);
+        record(&mut history, "a", false, 120);
+        assert_eq!(slots(&history), ["b", "a"]);
+        assert_eq!(history.evictions().max_age, 1);
+    }
+
+    #[test]
+    fn test_per_slot_eviction_keeps_newest_in_order() {
+        let mut history = History::new(Retention {
+            max_per_slot: Some(2),
+            ..Retention::default()
+        });
+        // "a" is updated far more often than "b", so most evictions land between the two
+        // retained entries of "b".
+        for i in 0..1_000 {
+            let slot = if i % 10 == 0 { "b" } else { "a" };
+            record(&mut history, slot, true, i);
+            assert!(history.len() <= 4);
+        }
+        let entries: Vec<_> = history
+            .iter()
+            .map(|entry| (entry.slot.as_str(), entry.timestamp))
+            .collect();
+        assert_eq!(entries, [("b", 980), ("b", 990), ("a", 998), ("a", 999)]);
+        assert_eq!(history[2].timestamp, 998);
+        assert_eq!(history.iter().rev().len(), 4);
+        assert_eq!(history.evictions().max_per_slot, 996);
+    }
+
+    #[test]
+    fn test_set_retention_keeps_newest_per_slot() {
+        let mut history = History::new(Retention::default());
+        for (i, slot) in ["a", "a", "b", "a"].into_iter().enumerate() {
+            record(&mut history, slot, true, i as i64);
+        }
+        history.set_retention(Retention {
+            max_per_slot: Some(1),
+            ..Retention::default()
+        });
+        assert_eq!(slots(&history), ["b", "a"]);
+        assert_eq!(history[1].timestamp, 3);
+        assert_eq!(history.evictions().total(), 2);
+    }
+
+    #[test]
+    fn test_state_at_after_per_slot_eviction() {
+        let mut history = History::new(Retention {
+            max_per_slot: Some(1),
+            ..Retention::default()
+        });
+        history.start_run([("a".to_string(), false), ("b".to_string(), false)], 0);
+        record(&mut history, "a", true, 10);
+        record(&mut history, "b", true, 20);
+        record(&mut history, "a", false, 30);
+
+        assert_eq!(history.horizon(), Some(10));
+        assert_eq!(history.state_at(5), None);
+        let at = |timestamp| history.state_at(timestamp).unwrap();
+        assert_eq!(
+            at(15),
+            HashMap::from([("a".to_string(), true), ("b".to_string(), false)])
+        );
+        assert_eq!(
+            at(30),
+            HashMap::from([("a".to_string(), false), ("b".to_string(), true)])
+        );
+        assert_eq!(history.last_change("a").unwrap().timestamp, 30);
+    }
+
+    #[test]
+    fn test_since_tails_by_sequence_number() {
+        let mut history = History::new(Retention {
+            max_entries: Some(3),
+            ..Retention::default()
+        });
+        // Identical timestamps are still ordered by `seq`.
+        for slot in ["a", "b", "c", "d", "e"] {
+            record(&mut history, slot, true, 42);
+        }
+        let seqs = |history: &History, from| {
+            history
+                .since(from)
+                .map(|entry| entry.seq)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(seqs(&history, 0), [3, 4, 5]);
+        assert_eq!(seqs(&history, 3), [4, 5]);
+        assert!(seqs(&history, 5).is_empty());
+
+        // Numbering carries on across setups.
+        history.start_run([], 100);
+        record(&mut history, "a", false, 100);
+        assert_eq!(seqs(&history, 5), [6]);
+    }
+}
@@ -4,13 +4,55 @@
 //!
 //! ## Features
 //! - Track multiple named boolean slots (flags) and their states.
-//! - Record a timestamped history of all slot changes.
-//! - Determine when all slots are active (true) via the `activated` field.
+//! - Track richer slot values through `XTState<K, V>` with any `V: SlotValue`, e.g. the
+//!   built-in `SlotStatus` (`Pending`, `InProgress`, `Done`, `Failed(reason)`, `Skipped`).
+//!   `state()` reports `Activated`, `Waiting` or `Failed` as soon as failed slots make the
+//!   policy unmeetable, and history entries record each transition's `previous` value.
+//! - `Progress` slots (a fraction or `n` of `total`) count as satisfied at 100%; `progress()`
+//!   and `weighted_progress(weights)` aggregate any slot values, and `eta()` extrapolates the
+//!   time to completion from the history.
+//! - Store values that cannot implement `SlotValue` (version strings, counters, foreign
+//!   enums) with `XTState::with_rules(ValueRules::new(initial, satisfied))`, where the
+//!   user-supplied `satisfied` predicate decides what counts towards activation; restoring,
+//!   replaying and verifying take the rules through their `_with_rules` variants.
+//! - Give each slot its own initial and target value with `SlotSetup::specs`, e.g.
+//!   `SlotSpec::new(true, false)` for "maintenance_mode must be off", and ask `explain()`
+//!   which slots are keeping the state from activating.
+//! - Set up slots with known initial values via `SlotSetup::values`; the values are recorded
+//!   as history entries and `is_activated()` reflects them immediately.
+//! - `setup(SlotSetup)` combines plain slots, initial values, targets, the policy and
+//!   forcing in one call, on `XTState` as well as on `SharedXTState` and `AsyncXTState`.
+//! - Key slots by any `K: Eq + Hash + Clone + Debug` (enums, integers, interned symbols);
+//!   the key type defaults to `String`, and history entries and accessors are typed by it.
+//! - Record a timestamped history of all slot changes, optionally bounded by a `Retention`
+//!   (max entries, max age, per-slot cap) with eviction counters exposed on `History`.
+//! - Every history entry carries a strictly increasing `seq` and a monotonic `elapsed` time;
+//!   tail new entries incrementally with `history().since(seq)`.
+//! - Every setup starts a new run with its own `run_id()`; a forced setup archives the previous
+//!   run's history instead of discarding it. Look runs up with `archived_run(id)` and bound the
+//!   archive with `set_archive_limit`.
+//! - Query the history with `history_for(id)`, `history_between(from, to)`, `last_change(id)`,
+//!   and reconstruct past slot maps and activation with `state_at(t)` / `activated_at(t)`.
+//! - Resolve a `SlotHandle` once with `handle(id)` and update through `update_by_handle`
+//!   without hashing or allocating on each update.
+//! - Add and remove slots at runtime with `add_slot` / `remove_slot`; membership changes are
+//!   recorded in the history and activation is recomputed.
+//! - Determine when all slots are active (true) via `is_activated()`, or pick another
+//!   `ActivationPolicy` (any, at-least-k, majority, all-except-optional) at setup time.
+//! - Express activation as a boolean condition over slot names, e.g.
+//!   `ActivationPolicy::condition("db && (cache || fallback_cache) && !maintenance")`.
+//! - Inspect slot values, pending slots and history through read-only accessors.
 //! - Thread-safe usage via the `ThreadSafeXTState` type alias (`Arc<Mutex<XTState>>`).
+//! - Blocking readiness waits via `SharedXTState`.
+//! - Validated `snapshot()` / `restore()` for persisting state across restarts.
+//! - Rebuild a state from its history log with `XTState::replay(slots, events)`, and check a
+//!   persisted snapshot against its log with `XTStateSnapshot::verify`.
+//! - Edge-triggered listeners via `on_slot_change`, `on_activated` and `on_deactivated`,
+//!   including the slot changes and activation edge caused by a setup.
 //!
 //! ## Example Usage
 //! ```rust
-//! use xtstate::{XTState, ThreadSafeXTState};
+//! use xtstate::{SharedXTState, ThreadSafeXTState, XTState};
 //! use std::collections::HashSet;
 //! use std::sync::{Arc, Mutex};
 //!
@@ -18,8 +60,11 @@
 //! let mut xt = XTState::new();
 //! xt.setup_slots(HashSet::from(["slot1".to_string(), "slot2".to_string()]), false);
 //! xt.update_callback("slot1".to_string(), true);
+//! assert_eq!(xt.pending_slots().count(), 1);
 //! xt.update_callback("slot2".to_string(), true);
-//! assert!(xt.activated);
+//! assert!(xt.is_activated());
+//! assert_eq!(xt.get("slot1"), Some(true));
+//! assert_eq!(xt.history().len(), 2);
 //!
 //! // Thread-safe usage
 //! let state: ThreadSafeXTState = Arc::new(Mutex::new(XTState::new()));
@@ -28,6 +73,16 @@
 //!     xt.setup_slots(HashSet::from(["slot1".to_string(), "slot2".to_string()]), false);
 //! }
 //! // ... spawn threads and update slots ...
+//!
+//! // Blocking until every slot is true
+//! let shared = Arc::new(SharedXTState::new());
+//! shared.setup_slots(HashSet::from(["db".to_string()]), false);
+//! let waiter = {
+//!     let shared = Arc::clone(&shared);
+//!     std::thread::spawn(move || shared.wait_until_activated())
+//! };
+//! shared.update_callback("db".to_string(), true);
+//! waiter.join().unwrap();
 //! ```
 //!
 //! ## Use Cases
@@ -37,89 +92,718 @@
 //! - Event synchronization
 //!
 //! ## Crate Features
-//! - Requires the `chrono` crate for timestamping history entries.
+//! - Requires the `chrono` crate for timestamping history entries through the default `SystemClock`.
+//!   Use `XTState::with_clock` with a `ManualClock` (or your own `Clock`) for deterministic timestamps.
+//! - `serde` (optional): implements `Serialize` / `Deserialize` for `XTState` and `XTStateSnapshot`.
+//!   Deserializing an `XTState` goes through `XTState::restore`, so inconsistent data is rejected.
+//! - `derive` (optional): `#[derive(XtSlots)]` on a struct of `bool` fields or a fieldless
+//!   enum generates the slot key set and a `<Name>State` wrapper with typed setters such as
+//!   `set_database(true)`, so unknown identifiers are rejected at compile time.
+//! - `tokio` (optional): enables `AsyncXTState`, exposing `activated()` and `slot_changed(id)`
+//!   futures plus a `watch` receiver of the activation flag for async services.
 //!
 //! ## Thread Safety
 //! Use the `ThreadSafeXTState` type alias for safe sharing and mutation across threads.
+//! `SharedXTState` pairs the mutex with a `Condvar` notified on every update, offering
+//! `wait_until_activated()`, `wait_until_activated_timeout(Duration)` and `wait_for_slot(id, value)`.
+//! For slot sets fixed up front, `AtomicXTState` offers the same semantics without any lock:
+//! each slot's value and version change in one atomic step, activation is an atomic pending
+//! counter and history is a lock-free queue drained with `drain_history()`.
+//! Listeners registered through `SharedXTState` or `AsyncXTState` run after the internal lock
+//! is released, so they may safely read or update the state again.
 
+use std::borrow::Borrow;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use archive::Archive;
+use observer::{Observers, SetupUpdate, Update};
+use policy::Tally;
+use slots::SlotTable;
+
+mod archive;
+#[cfg(feature = "tokio")]
+mod async_state;
+mod atomic;
+mod clock;
+mod error;
+mod explain;
+mod expr;
+mod history;
+mod observer;
+mod policy;
+mod replay;
+mod shared;
+mod slots;
+mod snapshot;
+mod typed;
+mod value;
+mod wrapper;
+
+#[cfg(feature = "tokio")]
+pub use async_state::AsyncXTState;
+pub use atomic::AtomicXTState;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use error::XTStateError;
+pub use explain::{Explanation, Mismatch};
+pub use expr::{Expr, ParseError};
+pub use history::{Evictions, History, HistoryEntry, HistoryEvent, HistoryIter, Retention};
+pub use observer::SlotChange;
+pub use policy::ActivationPolicy;
+pub use shared::SharedXTState;
+pub use slots::{SlotHandle, SlotSetup, SlotSpec};
+pub use snapshot::XTStateSnapshot;
+pub use typed::XtSlots;
+pub use value::{ActivationState, Progress, SlotStatus, SlotValue, ValueRules};
+#[cfg(feature = "derive")]
+pub use xtstate_derive::XtSlots;
+
+// Lets `#[derive(XtSlots)]` output, which names `::xtstate`, compile inside this crate's tests.
+#[cfg(all(test, feature = "derive"))]
+extern crate self as xtstate;
 
-pub type ThreadSafeXTState = Arc<Mutex<XTState>>;
+pub type ThreadSafeXTState<K = Identifier, V = bool> = Arc<Mutex<XTState<K, V>>>;
 
 type Identifier = String;
 
-pub struct XTState {
-    slots: HashMap<Identifier, bool>,
-    history: Vec<(Identifier, bool, i64)>,
+pub struct XTState<K = Identifier, V = bool> {
+    slots: SlotTable<K, V>,
+    history: History<K, V>,
+    archive: Archive<K, V>,
     is_setup: bool,
     activated: bool,
+    policy: ActivationPolicy<K>,
+    tally: Tally,
+    // Shared with the wrappers, which dispatch outside their lock; copied on registration.
+    observers: Arc<Observers<K, V>>,
+    clock: Arc<dyn Clock>,
+    rules: ValueRules<V>,
 }
 
-impl XTState {
+impl<K: Eq + Hash + Clone + fmt::Debug, V: SlotValue> XTState<K, V> {
     pub fn new() -> Self {
+        XTState::with_clock(Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        let mut xt_state = XTState::with_rules(ValueRules::default());
+        xt_state.clock = clock;
+        xt_state
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: Clone + PartialEq + fmt::Debug> XTState<K, V> {
+    /// A state over values that do not implement `SlotValue`, interpreted by `rules`.
+    pub fn with_rules(rules: ValueRules<V>) -> Self {
         XTState {
-            slots: HashMap::new(),
-            history: Vec::new(),
+            slots: SlotTable::default(),
+            history: History::new(Retention::default()),
+            archive: Archive::default(),
             is_setup: false,
             activated: false,
+            policy: ActivationPolicy::default(),
+            tally: Tally::default(),
+            observers: Arc::default(),
+            clock: Arc::new(SystemClock),
+            rules,
         }
     }
 
-    pub fn setup_slots(&mut self, slots: HashSet<Identifier>, force: bool) {
+    pub fn rules(&self) -> &ValueRules<V> {
+        &self.rules
+    }
+
+    pub fn is_setup(&self) -> bool {
+        self.is_setup
+    }
+
+    pub fn is_activated(&self) -> bool {
+        self.activated
+    }
+
+    /// `Failed` as soon as failed slots make the activation policy unmeetable, e.g. any
+    /// failed slot under `All`, or a failed required slot under `AllExcept`.
+    pub fn state(&self) -> ActivationState {
+        if self.activated {
+            ActivationState::Activated
+        } else if self.policy.is_failed_with(&self.tally, &self.slots) {
+            ActivationState::Failed
+        } else {
+            ActivationState::Waiting
+        }
+    }
+
+    pub fn get<Q>(&self, identifier: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.slots.get(identifier).cloned()
+    }
+
+    pub fn slots(&self) -> impl Iterator<Item = (&K, V)> {
+        self.slots
+            .iter()
+            .map(|slot| (&*slot.name, slot.value.clone()))
+    }
+
+    /// Mean `ValueRules::progress` over all slots, between 0.0 and 1.0.
+    pub fn progress(&self) -> f64 {
+        self.weighted_progress(&HashMap::new())
+    }
+
+    /// Like `progress`, with every slot weighted by `weights`; unlisted slots weigh 1.0.
+    pub fn weighted_progress(&self, weights: &HashMap<K, f64>) -> f64 {
+        value::weighted_progress(
+            self.slots
+                .iter()
+                .map(|slot| (&*slot.name, self.rules.progress(&slot.value))),
+            weights,
+        )
+    }
+
+    /// Time until `progress()` reaches 1.0 at the average rate since `History::horizon`,
+    /// counting from the slot values at that instant, so initial values given at setup are
+    /// not mistaken for progress. Slots added later start from the initial value. `None` if
+    /// no progress was made since then.
+    pub fn eta(&self) -> Option<Duration> {
+        self.weighted_eta(&HashMap::new())
+    }
+
+    pub fn weighted_eta(&self, weights: &HashMap<K, f64>) -> Option<Duration> {
+        let progress = self.weighted_progress(weights);
+        if progress >= 1.0 {
+            return Some(Duration::ZERO);
+        }
+        let horizon = self.history.horizon()?;
+        let base = self.history.state_at(horizon)?;
+        // Measure the start over the current slots too: slots added since count from the
+        // initial value, and removed ones drop out of both figures.
+        let start = value::weighted_progress(
+            self.slots.iter().map(|slot| {
+                let value = base.get(&*slot.name).unwrap_or(self.rules.initial());
+                (&*slot.name, self.rules.progress(value))
+            }),
+            weights,
+        );
+        let spent = self.clock.now_millis() - horizon;
+        if progress <= start || spent <= 0 {
+            return None;
+        }
+        let remaining = spent as f64 * (1.0 - progress) / (progress - start);
+        Some(Duration::from_secs_f64(remaining / 1000.0))
+    }
+
+    /// Slots that are not satisfied yet, including failed ones.
+    pub fn pending_slots(&self) -> impl Iterator<Item = &K> {
+        self.slots
+            .iter()
+            .filter(|slot| !slot.satisfied)
+            .map(|slot| &*slot.name)
+    }
+
+    pub fn failed_slots(&self) -> impl Iterator<Item = &K> {
+        self.slots
+            .iter()
+            .filter(|slot| slot.failed)
+            .map(|slot| &*slot.name)
+    }
+
+    pub fn handle<Q>(&self, identifier: &Q) -> Option<SlotHandle>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.slots.handle(identifier)
+    }
+
+    pub fn handles(&self) -> impl Iterator<Item = (&K, SlotHandle)> {
+        self.slots
+            .handles()
+            .map(|(slot, handle)| (&*slot.name, handle))
+    }
+
+    pub fn history(&self) -> &History<K, V> {
+        &self.history
+    }
+
+    pub fn history_for<'a, Q>(
+        &'a self,
+        identifier: &Q,
+    ) -> impl DoubleEndedIterator<Item = &'a HistoryEntry<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.history.for_slot(identifier)
+    }
+
+    /// Entries recorded in `from..to` (milliseconds, end exclusive).
+    pub fn history_between(
+        &self,
+        from: i64,
+        to: i64,
+    ) -> impl DoubleEndedIterator<Item = &HistoryEntry<K, V>> {
+        self.history.between(from, to)
+    }
+
+    /// The most recent history entry that changed the slot's value or membership.
+    pub fn last_change<Q>(&self, identifier: &Q) -> Option<&HistoryEntry<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.history.last_change(identifier)
+    }
+
+    /// The slot values at `timestamp`, or `None` if it predates the setup or the newest
+    /// evicted history entry.
+    pub fn state_at(&self, timestamp: i64) -> Option<HashMap<K, V>> {
+        self.history.state_at(timestamp)
+    }
+
+    /// Whether the state was activated at `timestamp`, under the current policy.
+    pub fn activated_at(&self, timestamp: i64) -> Option<bool> {
+        let slots = self.policy.slot_table(
+            &self.state_at(timestamp)?,
+            &self.slots.targets(),
+            &self.rules,
+        );
+        Some(!slots.is_empty() && self.policy.is_met(&slots))
+    }
+
+    /// The id of the current run, assigned at every setup; 0 before the first one.
+    pub fn run_id(&self) -> u64 {
+        self.history.run()
+    }
+
+    /// The history of an earlier run, if it is still archived.
+    pub fn archived_run(&self, run_id: u64) -> Option<&History<K, V>> {
+        self.archive.get(run_id)
+    }
+
+    /// Archived runs, oldest first.
+    pub fn archived_runs(&self) -> impl DoubleEndedIterator<Item = &History<K, V>> {
+        self.archive.iter()
+    }
+
+    /// Keeps at most `max_runs` archived runs, dropping the oldest first; `None` keeps all.
+    pub fn set_archive_limit(&mut self, max_runs: Option<usize>) {
+        self.archive.set_max_runs(max_runs);
+    }
+
+    /// How many archived runs the archive limit has dropped.
+    pub fn archive_evictions(&self) -> u64 {
+        self.archive.evicted()
+    }
+
+    /// Bounds the history from now on; entries that no longer fit are evicted right away.
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.history.set_retention(retention);
+    }
+
+    /// Evicts entries older than `Retention::max_age` as of the clock's current time. Ageing
+    /// otherwise only happens when a new entry is recorded.
+    pub fn prune_history(&mut self) {
+        self.history.prune(self.clock.now_millis());
+    }
+
+    pub fn policy(&self) -> &ActivationPolicy<K> {
+        &self.policy
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn on_slot_change(&mut self, listener: impl Fn(&SlotChange<K, V>) + Send + Sync + 'static) {
+        Arc::make_mut(&mut self.observers).on_slot_change(listener);
+    }
+
+    pub fn on_activated(&mut self, listener: impl Fn(&SlotChange<K, V>) + Send + Sync + 'static) {
+        Arc::make_mut(&mut self.observers).on_activated(listener);
+    }
+
+    pub fn on_deactivated(&mut self, listener: impl Fn(&SlotChange<K, V>) + Send + Sync + 'static) {
+        Arc::make_mut(&mut self.observers).on_deactivated(listener);
+    }
+
+    pub fn setup_slots(&mut self, slots: HashSet<K>, force: bool) {
+        if let Err(err) = self.try_setup_slots(slots, force) {
+            panic!("{}", err);
+        }
+    }
+
+    pub fn try_setup_slots(
+        &mut self,
+        slots: HashSet<K>,
+        force: bool,
+    ) -> Result<(), XTStateError<K>> {
+        self.try_setup_slots_with_policy(slots, ActivationPolicy::All, force)
+    }
+
+    pub fn setup_slots_with_policy(
+        &mut self,
+        slots: HashSet<K>,
+        policy: ActivationPolicy<K>,
+        force: bool,
+    ) {
+        if let Err(err) = self.try_setup_slots_with_policy(slots, policy, force) {
+            panic!("{}", err);
+        }
+    }
+
+    pub fn try_setup_slots_with_policy(
+        &mut self,
+        slots: HashSet<K>,
+        policy: ActivationPolicy<K>,
+        force: bool,
+    ) -> Result<(), XTStateError<K>> {
+        self.try_setup(SlotSetup::new().slots(slots).policy(policy).force(force))
+    }
+
+    pub fn setup(&mut self, setup: SlotSetup<K, V>) {
+        if let Err(err) = self.try_setup(setup) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Starts a new run. Slots without an initial value start at `ValueRules::initial`; the
+    /// others are updated to theirs once the run has started. Listeners see each slot change
+    /// from its value in the previous run, and an activation edge if setup caused one, which
+    /// is reported with the change of the slot listed last.
+    pub fn try_setup(&mut self, setup: SlotSetup<K, V>) -> Result<(), XTStateError<K>> {
+        let update = self.apply_setup(setup)?;
+        self.observers.dispatch_setup(&update);
+        Ok(())
+    }
+
+    fn apply_setup(
+        &mut self,
+        setup: SlotSetup<K, V>,
+    ) -> Result<SetupUpdate<K, V>, XTStateError<K>> {
+        let SlotSetup {
+            slots,
+            policy,
+            force,
+            record_initial,
+            ..
+        } = setup;
         if !force && self.is_setup {
-            panic!("xtstate is already set up. use force to override.");
+            return Err(XTStateError::AlreadySetUp);
         }
+        if slots.is_empty() {
+            return Err(XTStateError::NoSlots);
+        }
+        let names = slots.keys().cloned().collect();
+        policy.validate(&names)?;
+        let was_activated = self.activated;
+        let mut previous = Vec::new();
         if force && self.is_setup {
+            previous.extend(
+                self.slots
+                    .iter()
+                    .map(|slot| (Arc::clone(&slot.name), slot.value.clone())),
+            );
             self.is_setup = false;
             self.activated = false;
-            self.history.clear();
             self.slots.clear();
         }
-        for slot in slots {
-            self.slots.insert(slot, false);
+        let mut slots: Vec<_> = slots.into_iter().collect();
+        slots.sort_unstable_by_key(|(_, (listed, ..))| *listed);
+        let mut initial_values = Vec::new();
+        for (slot, (_, initial, target)) in slots {
+            let required = policy.is_required(&slot);
+            let (value, initial) = match initial {
+                Some(value) if !record_initial => (value, None),
+                initial => (self.rules.initial().clone(), initial),
+            };
+            let position = self
+                .slots
+                .insert(slot, value, target, required, &self.rules);
+            initial_values.extend(initial.map(|value| (position, value)));
+        }
+        let epoch = self.clock.now_millis();
+        let slots = self
+            .slots
+            .iter()
+            .map(|slot| ((*slot.name).clone(), slot.value.clone()));
+        let finished = self.history.start_run(slots, epoch);
+        if finished.run() > 0 {
+            self.archive.push(finished);
         }
+        self.policy = policy;
+        self.tally = Tally::new(&self.slots);
         self.is_setup = true;
+        self.activated = self.tally.total > 0 && self.policy.is_met_with(&self.tally, &self.slots);
+        for (position, value) in initial_values {
+            self.apply_at_time(position, value, epoch)?;
+        }
+
+        // Slots of the previous run that were dropped count as reset to the initial value.
+        // They come first, so that the last change is the one of the slot listed last.
+        let initial = self.rules.initial();
+        let (kept, dropped): (Vec<_>, Vec<_>) = previous
+            .into_iter()
+            .partition(|(slot, _)| self.slots.get(&**slot).is_some());
+        let kept: HashMap<_, _> = kept.into_iter().collect();
+        let mut changes: Vec<_> = dropped
+            .into_iter()
+            .map(|(slot, old)| SlotChange {
+                slot,
+                old,
+                new: initial.clone(),
+                timestamp: epoch,
+            })
+            .collect();
+        changes.extend(self.slots.iter().map(|slot| {
+            SlotChange {
+                slot: Arc::clone(&slot.name),
+                old: kept
+                    .get(&slot.name)
+                    .cloned()
+                    .unwrap_or_else(|| initial.clone()),
+                new: slot.value.clone(),
+                timestamp: epoch,
+            }
+        }));
+        Ok(SetupUpdate {
+            changes,
+            was_activated,
+            activated: self.activated,
+        })
     }
 
-    fn can_activate(&self) -> bool {
+    fn can_activate(&self) -> Result<bool, XTStateError<K>> {
         if !self.is_setup {
-            panic!("xtstate is not set up. call setup_slots first.");
+            return Err(XTStateError::NotSetUp);
         }
-        if self.slots.is_empty() {
-            panic!("no slots are defined. call setup_slots with valid slots.");
+        if self.tally.total == 0 {
+            return Err(XTStateError::NoSlots);
         }
 
-        self.slots.values().all(|&v| v)
+        Ok(self.policy.is_met_with(&self.tally, &self.slots))
+    }
+
+    pub fn update_callback(&mut self, identifier: K, value: V) {
+        if let Err(err) = self.try_update(identifier, value) {
+            panic!("{}", err);
+        }
+    }
+
+    pub fn try_update(&mut self, identifier: K, value: V) -> Result<(), XTStateError<K>> {
+        let update = self.apply(identifier, value)?;
+        self.observers.dispatch(&update);
+        Ok(())
+    }
+
+    pub fn update_by_handle(&mut self, handle: SlotHandle, value: V) {
+        if let Err(err) = self.try_update_by_handle(handle, value) {
+            panic!("{}", err);
+        }
     }
 
-    pub fn update_callback(&mut self, identifier: Identifier, value: bool) {
+    /// Updates the slot behind `handle` without hashing or cloning its identifier.
+    pub fn try_update_by_handle(
+        &mut self,
+        handle: SlotHandle,
+        value: V,
+    ) -> Result<(), XTStateError<K>> {
+        let update = self.apply_by_handle(handle, value)?;
+        self.observers.dispatch(&update);
+        Ok(())
+    }
+
+    fn apply(&mut self, identifier: K, value: V) -> Result<Update<K, V>, XTStateError<K>> {
         if !self.is_setup {
-            panic!("xtstate is not set up. call setup_slots first.");
+            return Err(XTStateError::NotSetUp);
         }
-        if !self.slots.contains_key(&identifier) {
-            panic!("identifier '{}' is not defined in the slots.", identifier);
+        match self.slots.position(&identifier) {
+            Some(position) => self.apply_at(position, value),
+            None => Err(XTStateError::UnknownSlot(identifier)),
         }
+    }
 
-        let epoch = chrono::Utc::now().timestamp_millis();
+    fn apply_by_handle(
+        &mut self,
+        handle: SlotHandle,
+        value: V,
+    ) -> Result<Update<K, V>, XTStateError<K>> {
+        match self.slots.resolve(handle) {
+            Some(position) => self.apply_at(position, value),
+            None => Err(XTStateError::StaleHandle),
+        }
+    }
 
-        self.history.push((identifier.clone(), value, epoch));
+    fn apply_at(&mut self, position: usize, value: V) -> Result<Update<K, V>, XTStateError<K>> {
+        self.apply_at_time(position, value, self.clock.now_millis())
+    }
 
-        if let Some(slot_value) = self.slots.get_mut(&identifier) {
-            *slot_value = value;
-        } else {
-            panic!("identifier '{}' not found in slots.", identifier);
+    fn apply_at_time(
+        &mut self,
+        position: usize,
+        value: V,
+        epoch: i64,
+    ) -> Result<Update<K, V>, XTStateError<K>> {
+        let slot = self.slots.slot_mut(position);
+        self.tally.remove(slot);
+        let old = slot.set(value.clone(), &self.rules);
+        self.tally.insert(slot);
+        let identifier = Arc::clone(&slot.name);
+
+        self.history.record(
+            Arc::clone(&identifier),
+            HistoryEvent::Updated(value.clone()),
+            Some(old.clone()),
+            epoch,
+            self.clock.elapsed(),
+        );
+
+        let was_activated = self.activated;
+        self.activated = self.can_activate()?;
+        Ok(Update {
+            change: SlotChange {
+                slot: identifier,
+                old,
+                new: value,
+                timestamp: epoch,
+            },
+            was_activated,
+            activated: self.activated,
+        })
+    }
+
+    pub fn add_slot(&mut self, identifier: K, initial: V) {
+        if let Err(err) = self.try_add_slot(identifier, initial) {
+            panic!("{}", err);
         }
+    }
 
-        if self.can_activate() {
-            self.activated = true;
-        } else {
-            self.activated = false;
+    pub fn try_add_slot(&mut self, identifier: K, initial: V) -> Result<(), XTStateError<K>> {
+        let update = self.apply_add(identifier, initial)?;
+        self.observers.dispatch(&update);
+        Ok(())
+    }
+
+    pub fn remove_slot<Q>(&mut self, identifier: &Q) -> V
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        match self.try_remove_slot(identifier) {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Removes a slot and returns its last value. Fails with `SlotInUse` while the
+    /// activation policy still refers to the slot.
+    pub fn try_remove_slot<Q>(&mut self, identifier: &Q) -> Result<V, XTStateError<K>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let update = self.apply_remove(identifier)?;
+        let value = update.change.old.clone();
+        self.observers.dispatch(&update);
+        Ok(value)
+    }
+
+    fn apply_add(&mut self, identifier: K, initial: V) -> Result<Update<K, V>, XTStateError<K>> {
+        if !self.is_setup {
+            return Err(XTStateError::NotSetUp);
         }
+        if self.slots.contains(&identifier) {
+            return Err(XTStateError::SlotExists(identifier));
+        }
+        let required = self.policy.is_required(&identifier);
+        let position = self
+            .slots
+            .insert(identifier, initial.clone(), None, required, &self.rules);
+        self.tally.insert(self.slots.slot(position));
+        let identifier = Arc::clone(&self.slots.slot(position).name);
+        let event = HistoryEvent::Added(initial.clone());
+        Ok(self.record_membership(identifier, initial, None, event))
+    }
+
+    fn apply_remove<Q>(&mut self, identifier: &Q) -> Result<Update<K, V>, XTStateError<K>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if !self.is_setup {
+            return Err(XTStateError::NotSetUp);
+        }
+        let Some(position) = self.slots.position(identifier) else {
+            return Err(XTStateError::UnknownSlot(identifier.to_owned()));
+        };
+        let name = Arc::clone(&self.slots.slot(position).name);
+        if self.policy.references(&name) {
+            return Err(XTStateError::SlotInUse((*name).clone()));
+        }
+        let remaining = self
+            .slots
+            .iter()
+            .filter(|slot| slot.name != name)
+            .map(|slot| (*slot.name).clone())
+            .collect();
+        self.policy.validate(&remaining)?;
+
+        let slot = self.slots.remove(identifier).unwrap();
+        self.tally.remove(&slot);
+        let previous = Some(slot.value.clone());
+        Ok(self.record_membership(slot.name, slot.value, previous, HistoryEvent::Removed))
+    }
+
+    fn record_membership(
+        &mut self,
+        identifier: Arc<K>,
+        value: V,
+        previous: Option<V>,
+        event: HistoryEvent<V>,
+    ) -> Update<K, V> {
+        let epoch = self.clock.now_millis();
+        self.history.record(
+            Arc::clone(&identifier),
+            event,
+            previous,
+            epoch,
+            self.clock.elapsed(),
+        );
+
+        let was_activated = self.activated;
+        self.activated = self.tally.total > 0 && self.policy.is_met_with(&self.tally, &self.slots);
+        Update {
+            change: SlotChange {
+                slot: identifier,
+                old: value.clone(),
+                new: value,
+                timestamp: epoch,
+            },
+            was_activated,
+            activated: self.activated,
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: Clone + PartialEq + fmt::Debug> fmt::Debug
+    for XTState<K, V>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("XTState")
+            .field("slots", &self.slots.to_map())
+            .field("history", &self.history)
+            .field("is_setup", &self.is_setup)
+            .field("activated", &self.activated)
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
     }
 }
 
-impl Default for XTState {
+impl<K: Eq + Hash + Clone + fmt::Debug, V: SlotValue> Default for XTState<K, V> {
     fn default() -> Self {
         XTState::new()
     }
@@ -128,7 +812,7 @@ impl Default for XTState {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
+    use std::collections::{HashMap, HashSet};
 
     #[test]
     fn test_basic() {
@@ -172,4 +856,588 @@ mod tests {
         let xt = state.lock().unwrap();
         assert!(xt.activated);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_try_api_errors() {
+        let mut xt_state = XTState::new();
+        assert_eq!(
+            xt_state.try_update("slot1".to_string(), true),
+            Err(XTStateError::NotSetUp)
+        );
+
+        xt_state
+            .try_setup_slots(HashSet::from(["slot1".to_string()]), false)
+            .unwrap();
+        assert_eq!(
+            xt_state.try_setup_slots(HashSet::from(["slot1".to_string()]), false),
+            Err(XTStateError::AlreadySetUp)
+        );
+        assert_eq!(
+            xt_state.try_update("unknown".to_string(), true),
+            Err(XTStateError::UnknownSlot("unknown".to_string()))
+        );
+        assert!(xt_state.history.is_empty());
+
+        xt_state.try_update("slot1".to_string(), true).unwrap();
+        assert!(xt_state.activated);
+    }
+
+    #[test]
+    fn test_read_accessors() {
+        let mut xt_state = XTState::new();
+        assert!(!xt_state.is_setup());
+        xt_state.setup_slots(
+            HashSet::from(["slot1".to_string(), "slot2".to_string()]),
+            false,
+        );
+        assert!(xt_state.is_setup());
+
+        xt_state.update_callback("slot1".to_string(), true);
+        assert_eq!(xt_state.get("slot1"), Some(true));
+        assert_eq!(xt_state.get("slot2"), Some(false));
+        assert_eq!(xt_state.get("slot3"), None);
+        assert_eq!(xt_state.slots().count(), 2);
+        assert_eq!(xt_state.pending_slots().collect::<Vec<_>>(), vec!["slot2"]);
+        assert!(!xt_state.is_activated());
+
+        let history = xt_state.history();
+        assert_eq!(history.len(), 1);
+        assert_eq!(*history[0].slot, "slot1");
+        assert_eq!(history[0].event, HistoryEvent::Updated(true));
+    }
+
+    #[test]
+    fn test_observers_are_edge_triggered() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let activations = Arc::new(AtomicUsize::new(0));
+        let deactivations = Arc::new(AtomicUsize::new(0));
+
+        let mut xt_state = XTState::new();
+        xt_state.setup_slots(
+            HashSet::from(["slot1".to_string(), "slot2".to_string()]),
+            false,
+        );
+        {
+            let changes = Arc::clone(&changes);
+            xt_state.on_slot_change(move |change| changes.lock().unwrap().push(change.clone()));
+        }
+        {
+            let activations = Arc::clone(&activations);
+            xt_state.on_activated(move |_| {
+                activations.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        {
+            let deactivations = Arc::clone(&deactivations);
+            xt_state.on_deactivated(move |change| {
+                assert_eq!(*change.slot, "slot1");
+                deactivations.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        xt_state.update_callback("slot1".to_string(), true);
+        xt_state.update_callback("slot2".to_string(), true);
+        xt_state.update_callback("slot2".to_string(), true);
+        xt_state.update_callback("slot1".to_string(), false);
+        xt_state.update_callback("slot1".to_string(), true);
+
+        assert_eq!(activations.load(Ordering::SeqCst), 2);
+        assert_eq!(deactivations.load(Ordering::SeqCst), 1);
+        let changes = changes.lock().unwrap();
+        assert_eq!(changes.len(), 4);
+        assert_eq!(*changes[0].slot, "slot1");
+        assert!(!changes[0].old && changes[0].new);
+        assert_eq!(changes[0].timestamp, xt_state.history()[0].timestamp);
+    }
+
+    #[test]
+    fn test_manual_clock_timestamps() {
+        use std::time::Duration;
+
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut xt_state = XTState::with_clock(clock.clone());
+        xt_state.setup_slots(
+            HashSet::from(["slot1".to_string(), "slot2".to_string()]),
+            false,
+        );
+
+        xt_state.update_callback("slot1".to_string(), true);
+        clock.advance(Duration::from_millis(250));
+        xt_state.update_callback("slot2".to_string(), true);
+        clock.set(5_000);
+        xt_state.update_callback("slot2".to_string(), false);
+
+        let timestamps: Vec<i64> = xt_state
+            .history()
+            .iter()
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![1_000, 1_250, 5_000]);
+    }
+
+    #[test]
+    fn test_history_retention() {
+        use std::time::Duration;
+
+        let clock = Arc::new(ManualClock::new(0));
+        let mut xt_state = XTState::with_clock(clock.clone());
+        xt_state.setup_slots(
+            HashSet::from(["flappy".to_string(), "steady".to_string()]),
+            false,
+        );
+        xt_state.update_callback("steady".to_string(), true);
+        for i in 0..10 {
+            xt_state.update_callback("flappy".to_string(), i % 2 == 0);
+        }
+        xt_state.set_retention(Retention {
+            max_age: Some(Duration::from_secs(60)),
+            max_per_slot: Some(3),
+            ..Retention::default()
+        });
+        assert_eq!(xt_state.history().len(), 4);
+        assert_eq!(xt_state.history().evictions().max_per_slot, 7);
+
+        let snapshot = xt_state.snapshot();
+        assert_eq!(snapshot.evictions.total(), 7);
+        assert_eq!(
+            XTState::restore(snapshot).unwrap().history(),
+            xt_state.history()
+        );
+
+        clock.advance(Duration::from_secs(61));
+        xt_state.prune_history();
+        assert!(xt_state.history().is_empty());
+        assert_eq!(xt_state.history().evictions().max_age, 4);
+
+        xt_state.setup_slots(HashSet::from(["slot1".to_string()]), true);
+        assert!(!xt_state.history().is_truncated());
+        assert_eq!(xt_state.history().retention().max_per_slot, Some(3));
+    }
+
+    #[test]
+    fn test_history_queries() {
+        let clock = Arc::new(ManualClock::new(100));
+        let mut xt_state = XTState::with_clock(clock.clone());
+        xt_state.setup_slots(
+            HashSet::from(["db".to_string(), "cache".to_string()]),
+            false,
+        );
+
+        for (at, slot, value) in [
+            (200, "db", true),
+            (300, "cache", true),
+            (400, "db", true),
+            (500, "db", false),
+        ] {
+            clock.set(at);
+            xt_state.update_callback(slot.to_string(), value);
+        }
+        clock.set(600);
+        xt_state.add_slot("queue".to_string(), true);
+
+        assert_eq!(xt_state.history_for("db").count(), 3);
+        assert_eq!(xt_state.history_between(300, 500).count(), 2);
+        assert_eq!(xt_state.last_change("db").unwrap().timestamp, 500);
+        assert_eq!(xt_state.last_change("cache").unwrap().timestamp, 300);
+        assert_eq!(xt_state.last_change("queue").unwrap().timestamp, 600);
+
+        assert_eq!(xt_state.state_at(99), None);
+        assert_eq!(
+            xt_state.state_at(100),
+            Some(HashMap::from([
+                ("db".to_string(), false),
+                ("cache".to_string(), false)
+            ]))
+        );
+        assert!(xt_state.state_at(450).unwrap()["db"]);
+        assert_eq!(xt_state.state_at(600).unwrap().len(), 3);
+        assert_eq!(xt_state.activated_at(250), Some(false));
+        assert_eq!(xt_state.activated_at(450), Some(true));
+        assert_eq!(xt_state.activated_at(500), Some(false));
+
+        // Evicted entries are folded into the base, so later instants stay answerable.
+        xt_state.set_retention(Retention {
+            max_entries: Some(2),
+            ..Retention::default()
+        });
+        assert_eq!(xt_state.history().horizon(), Some(400));
+        assert_eq!(xt_state.state_at(300), None);
+        assert_eq!(xt_state.activated_at(400), Some(true));
+        assert_eq!(
+            XTState::restore(xt_state.snapshot()).unwrap().state_at(550),
+            xt_state.state_at(550)
+        );
+    }
+
+    #[test]
+    fn test_forced_setup_archives_runs() {
+        let mut xt_state = XTState::new();
+        assert_eq!(xt_state.run_id(), 0);
+
+        for deployment in 1..=4 {
+            xt_state.setup_slots(HashSet::from(["slot1".to_string()]), true);
+            assert_eq!(xt_state.run_id(), deployment);
+            for _ in 0..deployment {
+                xt_state.update_callback("slot1".to_string(), true);
+            }
+        }
+
+        assert_eq!(xt_state.history().len(), 4);
+        let archived: Vec<u64> = xt_state.archived_runs().map(History::run).collect();
+        assert_eq!(archived, [1, 2, 3]);
+        assert_eq!(xt_state.archived_run(2).unwrap().len(), 2);
+        assert!(xt_state.archived_run(2).unwrap().started_at().is_some());
+        assert!(xt_state.archived_run(4).is_none());
+
+        // Sequence numbers keep increasing across runs.
+        let last_archived = xt_state.archived_run(3).unwrap().iter().last().unwrap().seq;
+        assert_eq!(xt_state.history()[0].seq, last_archived + 1);
+
+        xt_state.set_archive_limit(Some(2));
+        assert!(xt_state.archived_run(1).is_none());
+        assert_eq!(xt_state.archive_evictions(), 1);
+        xt_state.setup_slots(HashSet::from(["slot1".to_string()]), true);
+        let archived: Vec<u64> = xt_state.archived_runs().map(History::run).collect();
+        assert_eq!(archived, [3, 4]);
+        assert_eq!(XTState::restore(xt_state.snapshot()).unwrap().run_id(), 5);
+    }
+
+    #[test]
+    fn test_setup_with_policy() {
+        let mut xt_state = XTState::new();
+        xt_state.setup_slots_with_policy(
+            HashSet::from(["a".to_string(), "b".to_string(), "c".to_string()]),
+            ActivationPolicy::Majority,
+            false,
+        );
+
+        xt_state.update_callback("a".to_string(), true);
+        assert!(!xt_state.is_activated());
+        xt_state.update_callback("c".to_string(), true);
+        assert!(xt_state.is_activated());
+        assert_eq!(xt_state.policy(), &ActivationPolicy::Majority);
+        assert!(format!("{:?}", xt_state).contains("policy: Majority"));
+
+        assert!(matches!(
+            xt_state.try_setup_slots_with_policy(
+                HashSet::from(["a".to_string()]),
+                ActivationPolicy::AtLeast(2),
+                true,
+            ),
+            Err(XTStateError::InvalidPolicy(_))
+        ));
+        assert_eq!(xt_state.policy(), &ActivationPolicy::Majority);
+    }
+
+    #[test]
+    fn test_setup_with_condition() {
+        let mut xt_state = XTState::new();
+        let slots = HashSet::from([
+            "db".to_string(),
+            "cache".to_string(),
+            "fallback_cache".to_string(),
+            "maintenance".to_string(),
+        ]);
+        let policy =
+            ActivationPolicy::condition("db && (cache || fallback_cache) && !maintenance").unwrap();
+        xt_state.setup_slots_with_policy(slots.clone(), policy, false);
+
+        xt_state.update_callback("db".to_string(), true);
+        assert!(!xt_state.is_activated());
+        xt_state.update_callback("fallback_cache".to_string(), true);
+        assert!(xt_state.is_activated());
+        xt_state.update_callback("maintenance".to_string(), true);
+        assert!(!xt_state.is_activated());
+
+        let unknown = ActivationPolicy::condition("db && replica").unwrap();
+        assert_eq!(
+            xt_state.try_setup_slots_with_policy(slots, unknown, true),
+            Err(XTStateError::UnknownSlot("replica".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_add_and_remove_slots() {
+        let activations = Arc::new(Mutex::new(Vec::new()));
+        let mut xt_state = XTState::new();
+        xt_state.setup_slots(
+            HashSet::from(["worker1".to_string(), "worker2".to_string()]),
+            false,
+        );
+        {
+            let activations = Arc::clone(&activations);
+            xt_state.on_activated(move |change| {
+                activations.lock().unwrap().push((*change.slot).clone())
+            });
+        }
+
+        xt_state.update_callback("worker1".to_string(), true);
+        xt_state.add_slot("worker3".to_string(), true);
+        assert!(!xt_state.is_activated());
+
+        // Removing the last pending slot activates the state.
+        assert!(!xt_state.remove_slot("worker2"));
+        assert!(xt_state.is_activated());
+        assert_eq!(*activations.lock().unwrap(), vec!["worker2".to_string()]);
+
+        xt_state.add_slot("worker4".to_string(), false);
+        assert!(!xt_state.is_activated());
+        assert_eq!(
+            xt_state.try_add_slot("worker1".to_string(), false),
+            Err(XTStateError::SlotExists("worker1".to_string()))
+        );
+        assert_eq!(
+            xt_state.try_remove_slot("worker2"),
+            Err(XTStateError::UnknownSlot("worker2".to_string()))
+        );
+
+        let events: Vec<HistoryEvent> =
+            xt_state.history().iter().map(|entry| entry.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                HistoryEvent::Updated(true),
+                HistoryEvent::Added(true),
+                HistoryEvent::Removed,
+                HistoryEvent::Added(false),
+            ]
+        );
+        assert_eq!(xt_state.get("worker2"), None);
+        assert!(XTState::restore(xt_state.snapshot()).is_ok());
+    }
+
+    #[test]
+    fn test_remove_slot_referenced_by_policy() {
+        let mut xt_state = XTState::new();
+        xt_state.setup_slots_with_policy(
+            HashSet::from(["db".to_string(), "cache".to_string(), "extra".to_string()]),
+            ActivationPolicy::condition("db && cache").unwrap(),
+            false,
+        );
+
+        assert_eq!(
+            xt_state.try_remove_slot("cache"),
+            Err(XTStateError::SlotInUse("cache".to_string()))
+        );
+        assert_eq!(xt_state.try_remove_slot("extra"), Ok(false));
+    }
+
+    #[test]
+    fn test_tally_matches_full_scan() {
+        // A small linear congruential generator keeps the sequence deterministic.
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move |bound: usize| {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) as usize % bound
+        };
+
+        let names: Vec<Identifier> = (0..40).map(|i| format!("slot{}", i)).collect();
+        let policies = [
+            ActivationPolicy::All,
+            ActivationPolicy::Any,
+            ActivationPolicy::AtLeast(10),
+            ActivationPolicy::Majority,
+            ActivationPolicy::AllExcept(HashSet::from([names[0].clone(), names[1].clone()])),
+        ];
+        for policy in policies {
+            let mut xt_state = XTState::new();
+            xt_state.setup_slots_with_policy(
+                names[..20].iter().cloned().collect(),
+                policy.clone(),
+                false,
+            );
+            for _ in 0..2_000 {
+                let name = names[next(names.len())].clone();
+                let _ = match next(10) {
+                    0 => xt_state.try_add_slot(name, next(2) == 0),
+                    1 => xt_state.try_remove_slot(&name).map(|_| ()),
+                    _ => xt_state.try_update(name, next(3) != 0),
+                };
+
+                let expected = !xt_state.slots.is_empty() && policy.is_met(&xt_state.slots);
+                assert_eq!(xt_state.is_activated(), expected, "policy {:?}", policy);
+                assert_eq!(xt_state.tally, Tally::new(&xt_state.slots));
+            }
+        }
+    }
+
+    #[test]
+    fn test_update_by_handle() {
+        let mut xt_state = XTState::new();
+        xt_state.setup_slots(
+            HashSet::from(["slot1".to_string(), "slot2".to_string()]),
+            false,
+        );
+        let handles: HashMap<Identifier, SlotHandle> = xt_state
+            .handles()
+            .map(|(identifier, handle)| (identifier.clone(), handle))
+            .collect();
+        assert_eq!(xt_state.handle("slot1"), Some(handles["slot1"]));
+        assert_eq!(xt_state.handle("slot3"), None);
+
+        xt_state.update_by_handle(handles["slot1"], true);
+        xt_state.update_by_handle(handles["slot2"], true);
+        assert!(xt_state.is_activated());
+        assert_eq!(*xt_state.history()[1].slot, "slot2");
+
+        // Handles do not survive removal, even if the position is reused.
+        xt_state.remove_slot("slot2");
+        xt_state.add_slot("slot3".to_string(), false);
+        assert_eq!(
+            xt_state.try_update_by_handle(handles["slot2"], true),
+            Err(XTStateError::StaleHandle)
+        );
+
+        // Nor a forced setup with the same slot names.
+        xt_state.setup_slots(HashSet::from(["slot1".to_string()]), true);
+        assert_eq!(
+            xt_state.try_update_by_handle(handles["slot1"], true),
+            Err(XTStateError::StaleHandle)
+        );
+        assert_ne!(xt_state.handle("slot1"), Some(handles["slot1"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "identifier 'unknown' is not defined in the slots.")]
+    fn test_update_callback_panics_on_unknown_slot() {
+        let mut xt_state = XTState::new();
+        xt_state.setup_slots(HashSet::from(["slot1".to_string()]), false);
+        xt_state.update_callback("unknown".to_string(), true);
+    }
+
+    #[test]
+    fn test_enum_slot_keys() {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        enum Stage {
+            Fetch,
+            Build,
+            Deploy,
+        }
+
+        let mut xt_state: XTState<Stage> = XTState::new();
+        xt_state.setup_slots_with_policy(
+            HashSet::from([Stage::Fetch, Stage::Build, Stage::Deploy]),
+            ActivationPolicy::AllExcept(HashSet::from([Stage::Deploy])),
+            false,
+        );
+        xt_state.update_callback(Stage::Fetch, true);
+        xt_state.update_callback(Stage::Build, true);
+
+        assert!(xt_state.is_activated());
+        assert_eq!(xt_state.get(&Stage::Deploy), Some(false));
+        assert_eq!(
+            xt_state.pending_slots().collect::<Vec<_>>(),
+            vec![&Stage::Deploy]
+        );
+        assert_eq!(*xt_state.history()[1].slot, Stage::Build);
+        assert!(xt_state.remove_slot(&Stage::Build));
+        assert_eq!(
+            xt_state.try_update(Stage::Build, true),
+            Err(XTStateError::UnknownSlot(Stage::Build))
+        );
+
+        let condition = ActivationPolicy::condition("Fetch && !Deploy").unwrap();
+        let ActivationPolicy::Condition(expr) = condition else {
+            unreachable!()
+        };
+        let expr = expr
+            .try_map(&mut |name| match name.as_str() {
+                "Fetch" => Ok(Stage::Fetch),
+                "Deploy" => Ok(Stage::Deploy),
+                _ => Err(name),
+            })
+            .unwrap();
+        let mut xt_state = XTState::new();
+        xt_state.setup_slots_with_policy(
+            HashSet::from([Stage::Fetch, Stage::Deploy]),
+            ActivationPolicy::Condition(expr),
+            false,
+        );
+        xt_state.update_callback(Stage::Fetch, true);
+        assert!(xt_state.is_activated());
+    }
+
+    #[test]
+    fn test_setup_notifies_listeners() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut xt_state = XTState::new();
+        {
+            let events = Arc::clone(&events);
+            xt_state.on_slot_change(move |change| {
+                events
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} -> {}", change.slot, change.new));
+            });
+        }
+        {
+            let events = Arc::clone(&events);
+            xt_state.on_activated(move |change| {
+                events
+                    .lock()
+                    .unwrap()
+                    .push(format!("activated by {}", change.slot));
+            });
+        }
+        {
+            let events = Arc::clone(&events);
+            xt_state.on_deactivated(move |change| {
+                events
+                    .lock()
+                    .unwrap()
+                    .push(format!("deactivated by {}", change.slot));
+            });
+        }
+
+        xt_state.setup(SlotSetup::new().values([("db".to_string(), true)]));
+        xt_state.setup_slots(HashSet::from(["db".to_string()]), true);
+        // Changes follow the listing order, and the edge comes with the slot listed last.
+        let values = ["queue", "cache", "api"].map(|slot| (slot.to_string(), true));
+        xt_state.setup(SlotSetup::new().values(values).force(true));
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "db -> true",
+                "activated by db",
+                "db -> false",
+                "deactivated by db",
+                "queue -> true",
+                "cache -> true",
+                "api -> true",
+                "activated by api",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_setup_with_initial_values() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut xt_state = XTState::with_clock(clock);
+        xt_state.setup(
+            SlotSetup::new().values([("db".to_string(), true), ("cache".to_string(), true)]),
+        );
+        assert!(xt_state.is_activated());
+        assert_eq!(xt_state.history().len(), 2);
+        assert!(xt_state.history().iter().all(|entry| {
+            entry.event == HistoryEvent::Updated(true) && entry.previous == Some(false)
+        }));
+        assert_eq!(
+            xt_state.state_at(1_000),
+            Some(HashMap::from([
+                ("db".to_string(), true),
+                ("cache".to_string(), true)
+            ]))
+        );
+
+        let replayed = XTState::replay(
+            HashSet::from(["db".to_string(), "cache".to_string()]),
+            xt_state.history().iter().cloned(),
+        )
+        .unwrap();
+        assert!(replayed.is_activated());
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::Identifier;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotChange<K = Identifier, V = bool> {
+    pub slot: Arc<K>,
+    pub old: V,
+    pub new: V,
+    pub timestamp: i64,
+}
+
+type Listener<K, V> = Arc<dyn Fn(&SlotChange<K, V>) + Send + Sync>;
+
+/// The outcome of applying a single update, handed to `Observers::dispatch` once the
+/// state is no longer borrowed (or locked) so listeners may touch it again.
+pub(crate) struct Update<K, V = bool> {
+    pub(crate) change: SlotChange<K, V>,
+    pub(crate) was_activated: bool,
+    pub(crate) activated: bool,
+}
+
+/// The outcome of a setup: one change per slot, from its value in the previous run (or the
+/// initial value) to the value it starts the new run with. Slots dropped from the previous
+/// run come first, then the new ones in the order the setup listed them.
+pub(crate) struct SetupUpdate<K, V = bool> {
+    pub(crate) changes: Vec<SlotChange<K, V>>,
+    pub(crate) was_activated: bool,
+    pub(crate) activated: bool,
+}
+
+/// An update or setup waiting in a `DispatchQueue`.
+pub(crate) enum Notice<K, V = bool> {
+    Update(Update<K, V>),
+    Setup(SetupUpdate<K, V>),
+}
+
+impl<K, V: PartialEq> Notice<K, V> {
+    #[cfg(feature = "tokio")]
+    pub(crate) fn changes(&self) -> &[SlotChange<K, V>] {
+        match self {
+            Notice::Update(update) => std::slice::from_ref(&update.change),
+            Notice::Setup(update) => &update.changes,
+        }
+    }
+
+    fn dispatch(&self, observers: &Observers<K, V>) {
+        match self {
+            Notice::Update(update) => observers.dispatch(update),
+            Notice::Setup(update) => observers.dispatch_setup(update),
+        }
+    }
+}
+
+/// Lets the shared wrappers run listeners in the order their updates were applied without
+/// holding the state's lock while they run. Notices are pushed with the state still locked,
+/// then `drain` dispatches them once it is released.
+pub(crate) struct DispatchQueue<K, V = bool> {
+    queue: Mutex<Queue<K, V>>,
+}
+
+// Each notice with the listeners registered when it was applied.
+type Pending<K, V> = (Arc<Observers<K, V>>, Notice<K, V>);
+
+struct Queue<K, V> {
+    pending: VecDeque<Pending<K, V>>,
+    draining: bool,
+}
+
+/// Hands draining over to the next caller if a listener panics.
+struct Draining<'a, K, V>(&'a DispatchQueue<K, V>);
+
+impl<K, V> Drop for Draining<'_, K, V> {
+    fn drop(&mut self) {
+        self.0.lock().draining = false;
+    }
+}
+
+impl<K, V> DispatchQueue<K, V> {
+    fn lock(&self) -> MutexGuard<'_, Queue<K, V>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<K, V> Default for DispatchQueue<K, V> {
+    fn default() -> Self {
+        DispatchQueue {
+            queue: Mutex::new(Queue {
+                pending: VecDeque::new(),
+                draining: false,
+            }),
+        }
+    }
+}
+
+impl<K, V: PartialEq> DispatchQueue<K, V> {
+    /// Queues `notice` for the listeners in `observers`. Call it while the state is still
+    /// locked, so that notices queue in the order they were applied.
+    pub(crate) fn push(&self, observers: &Arc<Observers<K, V>>, notice: Notice<K, V>) {
+        self.lock()
+            .pending
+            .push_back((Arc::clone(observers), notice));
+    }
+
+    /// Dispatches queued notices until none are left, unless another call is already doing
+    /// so and will dispatch ours as well. A listener that updates the state therefore only
+    /// queues that update, which is dispatched once the listener returns.
+    pub(crate) fn drain(&self) {
+        {
+            let mut queue = self.lock();
+            if queue.draining {
+                return;
+            }
+            queue.draining = true;
+        }
+        let draining = Draining(self);
+        loop {
+            let (observers, notice) = {
+                let mut queue = self.lock();
+                match queue.pending.pop_front() {
+                    Some(next) => next,
+                    None => {
+                        queue.draining = false;
+                        break;
+                    }
+                }
+            };
+            notice.dispatch(&observers);
+        }
+        mem::forget(draining);
+    }
+}
+
+pub(crate) struct Observers<K, V = bool> {
+    slot_change: Vec<Listener<K, V>>,
+    activated: Vec<Listener<K, V>>,
+    deactivated: Vec<Listener<K, V>>,
+}
+
+impl<K, V> Default for Observers<K, V> {
+    fn default() -> Self {
+        Observers {
+            slot_change: Vec::new(),
+            activated: Vec::new(),
+            deactivated: Vec::new(),
+        }
+    }
+}
+
+// Derived `Clone` would require `K: Clone` and `V: Clone`; the listeners themselves are shared.
+impl<K, V> Clone for Observers<K, V> {
+    fn clone(&self) -> Self {
+        Observers {
+            slot_change: self.slot_change.clone(),
+            activated: self.activated.clone(),
+            deactivated: self.deactivated.clone(),
+        }
+    }
+}
+
+impl<K, V: PartialEq> Observers<K, V> {
+    pub(crate) fn on_slot_change(
+        &mut self,
+        listener: impl Fn(&SlotChange<K, V>) + Send + Sync + 'static,
+    ) {
+        self.slot_change.push(Arc::new(listener));
+    }
+
+    pub(crate) fn on_activated(
+        &mut self,
+        listener: impl Fn(&SlotChange<K, V>) + Send + Sync + 'static,
+    ) {
+        self.activated.push(Arc::new(listener));
+    }
+
+    pub(crate) fn on_deactivated(
+        &mut self,
+        listener: impl Fn(&SlotChange<K, V>) + Send + Sync + 'static,
+    ) {
+        self.deactivated.push(Arc::new(listener));
+    }
+
+    pub(crate) fn dispatch(&self, update: &Update<K, V>) {
+        self.dispatch_change(&update.change);
+        self.dispatch_edge(&update.change, update.was_activated, update.activated);
+    }
+
+    /// Reports every changed slot, then the activation edge, if any, with the last change,
+    /// which belongs to the slot the setup listed last.
+    pub(crate) fn dispatch_setup(&self, update: &SetupUpdate<K, V>) {
+        for change in &update.changes {
+            self.dispatch_change(change);
+        }
+        if let Some(last) = update.changes.last() {
+            self.dispatch_edge(last, update.was_activated, update.activated);
+        }
+    }
+
+    fn dispatch_change(&self, change: &SlotChange<K, V>) {
+        if change.old != change.new {
+            for listener in &self.slot_change {
+                listener(change);
+            }
+        }
+    }
+
+    fn dispatch_edge(&self, change: &SlotChange<K, V>, was_activated: bool, activated: bool) {
+        let edge = match (was_activated, activated) {
+            (false, true) => &self.activated,
+            (true, false) => &self.deactivated,
+            _ => return,
+        };
+        for listener in edge {
+            listener(change);
+        }
+    }
+}
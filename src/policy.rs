@@ -0,0 +1,309 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::slots::{Slot, SlotTable};
+use crate::{Expr, Identifier, ParseError, ValueRules, XTStateError};
+
+/// Decides, from the current slot values, whether an `XTState` is activated.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "K: Serialize + Eq + Hash",
+        deserialize = "K: Deserialize<'de> + Eq + Hash"
+    ))
+)]
+pub enum ActivationPolicy<K = Identifier> {
+    /// Every slot must be true.
+    #[default]
+    All,
+    /// At least one slot must be true.
+    Any,
+    /// At least `k` slots must be true.
+    AtLeast(usize),
+    /// Strictly more than half of the slots must be true.
+    Majority,
+    /// Every slot except the listed optional ones must be true.
+    AllExcept(HashSet<K>),
+    /// A boolean expression over slot names must hold.
+    Condition(Expr<K>),
+}
+
+// Derived equality would not carry the `Eq + Hash` bound that `HashSet<K>` needs.
+impl<K: Eq + Hash> PartialEq for ActivationPolicy<K> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ActivationPolicy::All, ActivationPolicy::All)
+            | (ActivationPolicy::Any, ActivationPolicy::Any)
+            | (ActivationPolicy::Majority, ActivationPolicy::Majority) => true,
+            (ActivationPolicy::AtLeast(a), ActivationPolicy::AtLeast(b)) => a == b,
+            (ActivationPolicy::AllExcept(a), ActivationPolicy::AllExcept(b)) => a == b,
+            (ActivationPolicy::Condition(a), ActivationPolicy::Condition(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<K: Eq + Hash> Eq for ActivationPolicy<K> {}
+
+/// Running counts kept alongside the slot map so that activation can be decided without
+/// scanning every slot on each update.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Tally {
+    pub(crate) total: usize,
+    pub(crate) pending: usize,
+    pub(crate) required_pending: usize,
+    pub(crate) failed: usize,
+    pub(crate) required_failed: usize,
+}
+
+impl Tally {
+    pub(crate) fn new<K: Eq + Hash + Clone, V: Clone + PartialEq>(
+        slots: &SlotTable<K, V>,
+    ) -> Tally {
+        let mut tally = Tally::default();
+        for slot in slots.iter() {
+            tally.insert(slot);
+        }
+        tally
+    }
+
+    pub(crate) fn insert<K, V>(&mut self, slot: &Slot<K, V>) {
+        self.total += 1;
+        if !slot.satisfied {
+            self.pending += 1;
+            self.required_pending += slot.required as usize;
+        }
+        if slot.failed {
+            self.failed += 1;
+            self.required_failed += slot.required as usize;
+        }
+    }
+
+    pub(crate) fn remove<K, V>(&mut self, slot: &Slot<K, V>) {
+        self.total -= 1;
+        if !slot.satisfied {
+            self.pending -= 1;
+            self.required_pending -= slot.required as usize;
+        }
+        if slot.failed {
+            self.failed -= 1;
+            self.required_failed -= slot.required as usize;
+        }
+    }
+}
+
+impl ActivationPolicy {
+    pub fn condition(source: &str) -> Result<ActivationPolicy, ParseError> {
+        Expr::parse(source).map(ActivationPolicy::Condition)
+    }
+}
+
+impl<K: Eq + Hash + Clone> ActivationPolicy<K> {
+    pub(crate) fn validate(&self, slots: &HashSet<K>) -> Result<(), XTStateError<K>> {
+        match self {
+            ActivationPolicy::AtLeast(k) if *k == 0 || *k > slots.len() => {
+                Err(XTStateError::InvalidPolicy(format!(
+                    "at least {} requires between 1 and {} slots",
+                    k,
+                    slots.len()
+                )))
+            }
+            ActivationPolicy::AllExcept(optional) => {
+                if let Some(unknown) = optional.iter().find(|slot| !slots.contains(*slot)) {
+                    return Err(XTStateError::UnknownSlot(unknown.clone()));
+                }
+                if optional.len() == slots.len() {
+                    return Err(XTStateError::InvalidPolicy(
+                        "every slot is optional".to_string(),
+                    ));
+                }
+                Ok(())
+            }
+            ActivationPolicy::Condition(expr) => {
+                match expr
+                    .identifiers()
+                    .into_iter()
+                    .find(|slot| !slots.contains(*slot))
+                {
+                    Some(unknown) => Err(XTStateError::UnknownSlot(unknown.clone())),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Builds a slot table from plain values and targets, marking slots required as this
+    /// policy does.
+    pub(crate) fn slot_table<V: Clone + PartialEq>(
+        &self,
+        values: &HashMap<K, V>,
+        targets: &HashMap<K, V>,
+        rules: &ValueRules<V>,
+    ) -> SlotTable<K, V> {
+        let mut slots = SlotTable::default();
+        for (identifier, value) in values {
+            let target = targets.get(identifier).cloned();
+            let required = self.is_required(identifier);
+            slots.insert(identifier.clone(), value.clone(), target, required, rules);
+        }
+        slots
+    }
+
+    pub(crate) fn references(&self, slot: &K) -> bool {
+        match self {
+            ActivationPolicy::AllExcept(optional) => optional.contains(slot),
+            ActivationPolicy::Condition(expr) => expr.identifiers().contains(slot),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn is_required(&self, slot: &K) -> bool {
+        match self {
+            ActivationPolicy::AllExcept(optional) => !optional.contains(slot),
+            _ => true,
+        }
+    }
+
+    /// Same answer as `is_met`, but in constant time for every policy except `Condition`,
+    /// whose cost depends only on the size of the expression.
+    pub(crate) fn is_met_with<V: Clone + PartialEq>(
+        &self,
+        tally: &Tally,
+        slots: &SlotTable<K, V>,
+    ) -> bool {
+        let satisfied = tally.total - tally.pending;
+        match self {
+            ActivationPolicy::All => tally.pending == 0,
+            ActivationPolicy::Any => satisfied > 0,
+            ActivationPolicy::AtLeast(k) => satisfied >= *k,
+            ActivationPolicy::Majority => satisfied * 2 > tally.total,
+            ActivationPolicy::AllExcept(_) => tally.required_pending == 0,
+            ActivationPolicy::Condition(expr) => expr.evaluate(&|slot| slots.is_satisfied(slot)),
+        }
+    }
+
+    /// Whether the failed slots alone make this policy impossible to meet, i.e. it would stay
+    /// unmet even if every other slot became satisfied.
+    pub(crate) fn is_failed_with<V: Clone + PartialEq>(
+        &self,
+        tally: &Tally,
+        slots: &SlotTable<K, V>,
+    ) -> bool {
+        let reachable = tally.total - tally.failed;
+        match self {
+            ActivationPolicy::All => tally.failed > 0,
+            ActivationPolicy::Any => tally.total > 0 && reachable == 0,
+            ActivationPolicy::AtLeast(k) => reachable < *k,
+            ActivationPolicy::Majority => reachable * 2 <= tally.total,
+            ActivationPolicy::AllExcept(_) => tally.required_failed > 0,
+            // Failed slots are known to be false; every other slot could still go either way.
+            ActivationPolicy::Condition(expr) => {
+                let value_of = |slot: &K| match slots.position(slot) {
+                    Some(position) if !slots.slot(position).failed => None,
+                    _ => Some(false),
+                };
+                expr.evaluate_partial(&value_of) == Some(false)
+            }
+        }
+    }
+
+    pub(crate) fn is_met<V: Clone + PartialEq>(&self, slots: &SlotTable<K, V>) -> bool {
+        let satisfied = || slots.iter().filter(|slot| slot.satisfied).count();
+        match self {
+            ActivationPolicy::All => slots.iter().all(|slot| slot.satisfied),
+            ActivationPolicy::Any => slots.iter().any(|slot| slot.satisfied),
+            ActivationPolicy::AtLeast(k) => satisfied() >= *k,
+            ActivationPolicy::Majority => satisfied() * 2 > slots.len(),
+            ActivationPolicy::AllExcept(optional) => slots
+                .iter()
+                .all(|slot| slot.satisfied || optional.contains(&*slot.name)),
+            ActivationPolicy::Condition(expr) => expr.evaluate(&|slot| slots.is_satisfied(slot)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slots(values: &[(&str, bool)]) -> SlotTable<Identifier> {
+        let mut table = SlotTable::default();
+        for &(slot, value) in values {
+            table.insert(slot.to_string(), value, None, true, &ValueRules::default());
+        }
+        table
+    }
+
+    #[test]
+    fn test_policies() {
+        let values = slots(&[("a", true), ("b", true), ("c", false)]);
+
+        assert!(!ActivationPolicy::All.is_met(&values));
+        assert!(ActivationPolicy::Any.is_met(&values));
+        assert!(ActivationPolicy::AtLeast(2).is_met(&values));
+        assert!(!ActivationPolicy::AtLeast(3).is_met(&values));
+        assert!(ActivationPolicy::Majority.is_met(&values));
+        assert!(ActivationPolicy::AllExcept(HashSet::from(["c".to_string()])).is_met(&values));
+        assert!(!ActivationPolicy::AllExcept(HashSet::from(["a".to_string()])).is_met(&values));
+        assert!(
+            ActivationPolicy::condition("a && (c || b)")
+                .unwrap()
+                .is_met(&values)
+        );
+        assert!(
+            !ActivationPolicy::condition("a && !b")
+                .unwrap()
+                .is_met(&values)
+        );
+    }
+
+    #[test]
+    fn test_failed_slots_make_policies_unmeetable() {
+        use crate::SlotStatus;
+
+        let rules = ValueRules::default();
+        let mut slots: SlotTable<Identifier, SlotStatus> = SlotTable::default();
+        let failed = SlotStatus::Failed("down".to_string());
+        slots.insert("a".to_string(), failed, None, true, &rules);
+        slots.insert("b".to_string(), SlotStatus::Pending, None, true, &rules);
+        slots.insert("c".to_string(), SlotStatus::InProgress, None, false, &rules);
+        let tally = Tally::new(&slots);
+        let failed = |policy: ActivationPolicy| policy.is_failed_with(&tally, &slots);
+
+        assert!(failed(ActivationPolicy::All));
+        assert!(!failed(ActivationPolicy::Any));
+        assert!(!failed(ActivationPolicy::AtLeast(2)));
+        assert!(failed(ActivationPolicy::AtLeast(3)));
+        assert!(!failed(ActivationPolicy::Majority));
+        assert!(failed(ActivationPolicy::condition("a && b").unwrap()));
+        assert!(!failed(ActivationPolicy::condition("a || b").unwrap()));
+        assert!(!failed(ActivationPolicy::condition("!a && c").unwrap()));
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        let names: HashSet<Identifier> = HashSet::from(["a".to_string(), "b".to_string()]);
+
+        assert!(ActivationPolicy::AtLeast(2).validate(&names).is_ok());
+        assert!(matches!(
+            ActivationPolicy::AtLeast(3).validate(&names),
+            Err(XTStateError::InvalidPolicy(_))
+        ));
+        assert_eq!(
+            ActivationPolicy::AllExcept(HashSet::from(["z".to_string()])).validate(&names),
+            Err(XTStateError::UnknownSlot("z".to_string()))
+        );
+        assert_eq!(
+            ActivationPolicy::condition("a && !z")
+                .unwrap()
+                .validate(&names),
+            Err(XTStateError::UnknownSlot("z".to_string()))
+        );
+    }
+}
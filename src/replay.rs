@@ -0,0 +1,320 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::{
+    ActivationPolicy, Clock, HistoryEntry, HistoryEvent, SlotSetup, SlotValue, SystemClock,
+    ValueRules, XTState, XTStateError, XTStateSnapshot,
+};
+
+/// Reports the timestamp and elapsed time of the entry being replayed.
+struct ReplayClock {
+    current: Mutex<(i64, Duration)>,
+}
+
+impl ReplayClock {
+    fn set(&self, timestamp: i64, elapsed: Duration) {
+        *self.current.lock().unwrap_or_else(PoisonError::into_inner) = (timestamp, elapsed);
+    }
+
+    fn current(&self) -> (i64, Duration) {
+        *self.current.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clock for ReplayClock {
+    fn now_millis(&self) -> i64 {
+        self.current().0
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.current().1
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: SlotValue> XTState<K, V> {
+    /// Rebuilds a state by setting up `slots` and applying `events` in order, as recorded in
+    /// `history()`. Replayed entries keep their original timestamps, elapsed times and
+    /// sequence numbers (entries with `seq` 0 are numbered afresh); the returned state records
+    /// new entries with a `SystemClock`.
+    pub fn replay(
+        slots: HashSet<K>,
+        events: impl IntoIterator<Item = HistoryEntry<K, V>>,
+    ) -> Result<XTState<K, V>, XTStateError<K>> {
+        XTState::replay_with_policy(slots, ActivationPolicy::All, events)
+    }
+
+    pub fn replay_with_policy(
+        slots: HashSet<K>,
+        policy: ActivationPolicy<K>,
+        events: impl IntoIterator<Item = HistoryEntry<K, V>>,
+    ) -> Result<XTState<K, V>, XTStateError<K>> {
+        XTState::replay_with(SlotSetup::new().slots(slots).policy(policy), events)
+    }
+
+    /// Replays `events` over any setup, e.g. one with `SlotSetup::targets`. Initial values in
+    /// `setup` are the state the log starts from, like `History::base` of a trimmed history,
+    /// and are not recorded; the history of a setup with initial values already holds them.
+    ///
+    /// Fails on the first event that references an unknown slot, adds an existing one, does
+    /// not increase the sequence number, or expects a `previous` value other than the
+    /// replayed one. Events with `seq` 0 must instead not be older than the event before them.
+    pub fn replay_with(
+        setup: SlotSetup<K, V>,
+        events: impl IntoIterator<Item = HistoryEntry<K, V>>,
+    ) -> Result<XTState<K, V>, XTStateError<K>> {
+        XTState::replay_with_rules(setup, ValueRules::default(), events)
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: Clone + PartialEq + fmt::Debug> XTState<K, V> {
+    /// Like `replay_with`, for values interpreted by `rules`.
+    pub fn replay_with_rules(
+        mut setup: SlotSetup<K, V>,
+        rules: ValueRules<V>,
+        events: impl IntoIterator<Item = HistoryEntry<K, V>>,
+    ) -> Result<XTState<K, V>, XTStateError<K>> {
+        setup.record_initial = false;
+        let mut events = events.into_iter().peekable();
+        let start = events.peek().map_or((0, Duration::ZERO), |entry| {
+            (entry.timestamp, entry.elapsed)
+        });
+        let clock = Arc::new(ReplayClock {
+            current: Mutex::new(start),
+        });
+        let mut xt_state = XTState::with_rules(rules);
+        xt_state.set_clock(clock.clone());
+        xt_state.try_setup(setup)?;
+
+        // Sequence numbers order the log; timestamps may step back with the wall clock, so
+        // they are only checked for entries without one.
+        let mut previous = start.0;
+        for (index, entry) in events.enumerate() {
+            if entry.seq == 0 && entry.timestamp < previous {
+                return Err(XTStateError::InvalidHistory(format!(
+                    "entry {} at {} is older than the entry before it at {}",
+                    index, entry.timestamp, previous
+                )));
+            }
+            previous = entry.timestamp;
+            if entry.seq != 0 {
+                if entry.seq < xt_state.history.next_seq() {
+                    return Err(XTStateError::InvalidHistory(format!(
+                        "entry {} has sequence number {}, expected at least {}",
+                        index,
+                        entry.seq,
+                        xt_state.history.next_seq()
+                    )));
+                }
+                xt_state.history.skip_to(entry.seq);
+            }
+            if let Some(expected) = &entry.previous {
+                let current = xt_state.slots.get(&*entry.slot);
+                if current.is_some_and(|current| current != expected) {
+                    return Err(XTStateError::InvalidHistory(format!(
+                        "entry {} expects {:?} before it, but the slot holds {:?}",
+                        index, expected, current
+                    )));
+                }
+            }
+            clock.set(entry.timestamp, entry.elapsed);
+            let slot = Arc::unwrap_or_clone(entry.slot);
+            match entry.event {
+                HistoryEvent::Updated(value) => xt_state.apply(slot, value).map(drop)?,
+                HistoryEvent::Added(value) => xt_state.apply_add(slot, value).map(drop)?,
+                HistoryEvent::Removed => xt_state.apply_remove(&slot).map(drop)?,
+            }
+        }
+        xt_state.set_clock(Arc::new(SystemClock));
+        Ok(xt_state)
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: SlotValue> XTStateSnapshot<K, V> {
+    /// Checks that replaying `events` over `slots` with this snapshot's policy and targets
+    /// ends in exactly the slot values and activation stored in the snapshot. Snapshots with a
+    /// `history_horizon` replay from `history_base` instead of `slots`, so that a history
+    /// trimmed by `Retention` verifies too.
+    pub fn verify(
+        &self,
+        slots: HashSet<K>,
+        events: impl IntoIterator<Item = HistoryEntry<K, V>>,
+    ) -> Result<(), XTStateError<K>> {
+        self.verify_with_rules(slots, ValueRules::default(), events)
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: Clone + PartialEq + fmt::Debug> XTStateSnapshot<K, V> {
+    /// Like `verify`, for values interpreted by `rules`.
+    pub fn verify_with_rules(
+        &self,
+        slots: HashSet<K>,
+        rules: ValueRules<V>,
+        events: impl IntoIterator<Item = HistoryEntry<K, V>>,
+    ) -> Result<(), XTStateError<K>> {
+        let setup = match self.history_horizon {
+            Some(_) => SlotSetup::new().values(self.history_base.clone()),
+            None => SlotSetup::new().slots(slots),
+        };
+        let mut setup = setup.policy(self.policy.clone());
+        for (slot, (_, _, target)) in &mut setup.slots {
+            *target = self.targets.get(slot).cloned();
+        }
+        let replayed = XTState::replay_with_rules(setup, rules, events)?;
+        if replayed.slots.to_map() != self.slots {
+            return Err(XTStateError::InvalidSnapshot(
+                "slot values do not match the replayed log".to_string(),
+            ));
+        }
+        if replayed.activated != self.activated {
+            return Err(XTStateError::InvalidSnapshot(
+                "activated does not match the replayed log".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ManualClock, Retention, SlotSpec};
+
+    fn names(slots: &[&str]) -> HashSet<String> {
+        slots.iter().map(|slot| slot.to_string()).collect()
+    }
+
+    #[test]
+    fn test_replay_matches_original() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut xt_state = XTState::with_clock(clock.clone());
+        xt_state.setup_slots(names(&["db", "cache"]), false);
+        xt_state.update_callback("db".to_string(), true);
+        clock.set(1_500);
+        xt_state.add_slot("queue".to_string(), true);
+        xt_state.update_callback("cache".to_string(), true);
+        xt_state.update_callback("db".to_string(), false);
+        clock.set(2_000);
+        xt_state.remove_slot("db");
+
+        let log = xt_state.history().to_vec();
+        let replayed = XTState::replay(names(&["db", "cache"]), log.clone()).unwrap();
+        assert!(replayed.is_activated());
+        assert_eq!(replayed.snapshot().slots, xt_state.snapshot().slots);
+        assert_eq!(replayed.history().to_vec(), log);
+        assert_eq!(replayed.state_at(1_500), xt_state.state_at(1_500));
+        assert!(replayed.clock.now_millis() > 2_000);
+
+        let snapshot = xt_state.snapshot();
+        assert_eq!(
+            snapshot.verify(names(&["db", "cache"]), log.clone()),
+            Ok(())
+        );
+        assert!(matches!(
+            snapshot.verify(names(&["db", "cache"]), log[..4].to_vec()),
+            Err(XTStateError::InvalidSnapshot(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_with_targets() {
+        let mut xt_state = XTState::new();
+        xt_state.setup(SlotSetup::new().specs([
+            ("maintenance".to_string(), SlotSpec::new(true, false)),
+            ("db".to_string(), SlotSpec::new(false, true)),
+        ]));
+        xt_state.update_callback("maintenance".to_string(), false);
+        xt_state.update_callback("db".to_string(), true);
+        assert!(xt_state.is_activated());
+
+        let slots = names(&["maintenance", "db"]);
+        let log = xt_state.history().to_vec();
+        assert_eq!(
+            xt_state.snapshot().verify(slots.clone(), log.clone()),
+            Ok(())
+        );
+
+        let targets = [("maintenance".to_string(), false), ("db".to_string(), true)];
+        let replayed = XTState::replay_with(SlotSetup::new().targets(targets), log).unwrap();
+        assert!(replayed.is_activated());
+        assert_eq!(replayed.explain(), xt_state.explain());
+    }
+
+    #[test]
+    fn test_verify_trimmed_history() {
+        let mut xt_state = XTState::new();
+        xt_state.set_retention(Retention {
+            max_entries: Some(1),
+            ..Retention::default()
+        });
+        xt_state.setup_slots(names(&["db", "cache"]), false);
+        xt_state.update_callback("db".to_string(), true);
+        xt_state.update_callback("cache".to_string(), true);
+        xt_state.update_callback("db".to_string(), false);
+
+        let log = xt_state.history().to_vec();
+        assert_eq!(log.len(), 1);
+        let snapshot = xt_state.snapshot();
+        assert_eq!(snapshot.verify(names(&["db", "cache"]), log), Ok(()));
+    }
+
+    #[test]
+    fn test_replay_rejects_invalid_logs() {
+        let entry = |slot: &str, event, timestamp| HistoryEntry {
+            slot: Arc::new(slot.to_string()),
+            event,
+            previous: None,
+            timestamp,
+            seq: 0,
+            elapsed: Duration::ZERO,
+        };
+
+        assert_eq!(
+            XTState::replay(names(&["a"]), [entry("b", HistoryEvent::Updated(true), 0)])
+                .unwrap_err(),
+            XTStateError::UnknownSlot("b".to_string())
+        );
+        assert_eq!(
+            XTState::replay(names(&["a"]), [entry("a", HistoryEvent::Added(true), 0)]).unwrap_err(),
+            XTStateError::SlotExists("a".to_string())
+        );
+        assert!(matches!(
+            XTState::replay(
+                names(&["a"]),
+                [
+                    entry("a", HistoryEvent::Updated(true), 10),
+                    entry("a", HistoryEvent::Updated(false), 5),
+                ]
+            ),
+            Err(XTStateError::InvalidHistory(_))
+        ));
+
+        let mut first = entry("a", HistoryEvent::Updated(true), 10);
+        first.seq = 7;
+        let mut second = entry("a", HistoryEvent::Updated(false), 10);
+        second.seq = 7;
+        assert!(matches!(
+            XTState::replay(names(&["a"]), [first.clone(), second.clone()]),
+            Err(XTStateError::InvalidHistory(_))
+        ));
+
+        // An entry expecting another value than the replayed one reveals a gap in the log.
+        let mut gap = entry("a", HistoryEvent::Updated(false), 0);
+        gap.previous = Some(false);
+        assert!(matches!(
+            XTState::replay(
+                names(&["a"]),
+                [entry("a", HistoryEvent::Updated(true), 0), gap]
+            ),
+            Err(XTStateError::InvalidHistory(_))
+        ));
+
+        // A wall clock stepping back is fine as long as the sequence numbers increase.
+        second.seq = 8;
+        second.timestamp = 5;
+        let replayed = XTState::replay(names(&["a"]), [first, second]).unwrap();
+        assert_eq!(replayed.history()[1].timestamp, 5);
+    }
+}
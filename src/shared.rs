@@ -0,0 +1,259 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::observer::{DispatchQueue, Notice};
+use crate::{Identifier, SlotValue, XTState, XTStateError};
+
+/// An `XTState` behind a mutex paired with a condition variable, so threads can block
+/// until the state activates or a slot reaches a given value instead of polling the lock.
+///
+/// Listeners run after the lock is released, in the order the updates were applied; an
+/// update may return before its listeners ran if another thread is still running earlier ones.
+pub struct SharedXTState<K = Identifier, V = bool> {
+    state: Mutex<XTState<K, V>>,
+    changed: Condvar,
+    notices: DispatchQueue<K, V>,
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: SlotValue> SharedXTState<K, V> {
+    pub fn new() -> Self {
+        SharedXTState::from(XTState::new())
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: Clone + PartialEq + fmt::Debug> SharedXTState<K, V> {
+    pub fn read<R>(&self, f: impl FnOnce(&XTState<K, V>) -> R) -> R {
+        f(&self.lock())
+    }
+
+    crate::wrapper::mutating_api!();
+
+    pub fn wait_until_activated(&self) {
+        let guard = self.lock();
+        let _guard = self
+            .changed
+            .wait_while(guard, |xt| !xt.is_activated())
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+
+    /// Returns `false` if the timeout elapsed before the state activated.
+    pub fn wait_until_activated_timeout(&self, timeout: Duration) -> bool {
+        let guard = self.lock();
+        let (guard, _) = self
+            .changed
+            .wait_timeout_while(guard, timeout, |xt| !xt.is_activated())
+            .unwrap_or_else(PoisonError::into_inner);
+        guard.is_activated()
+    }
+
+    pub fn wait_for_slot<Q>(&self, identifier: &Q, value: V) -> Result<(), XTStateError<K>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mut guard = self.lock();
+        loop {
+            match guard.get(identifier) {
+                Some(current) if current == value => return Ok(()),
+                Some(_) => {}
+                None if guard.is_setup() => {
+                    return Err(XTStateError::UnknownSlot(identifier.to_owned()));
+                }
+                None => return Err(XTStateError::NotSetUp),
+            }
+            guard = self
+                .changed
+                .wait(guard)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn write<R>(&self, f: impl FnOnce(&mut XTState<K, V>) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    fn apply_with(
+        &self,
+        f: impl FnOnce(&mut XTState<K, V>) -> Result<Notice<K, V>, XTStateError<K>>,
+    ) -> Result<(), XTStateError<K>> {
+        {
+            let mut xt = self.lock();
+            let notice = f(&mut xt)?;
+            self.notices.push(&xt.observers, notice);
+        }
+        self.changed.notify_all();
+        self.notices.drain();
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, XTState<K, V>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: SlotValue> Default for SharedXTState<K, V> {
+    fn default() -> Self {
+        SharedXTState::new()
+    }
+}
+
+impl<K, V> From<XTState<K, V>> for SharedXTState<K, V> {
+    fn from(state: XTState<K, V>) -> Self {
+        SharedXTState {
+            state: Mutex::new(state),
+            changed: Condvar::new(),
+            notices: DispatchQueue::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ActivationPolicy, SlotChange, SlotSetup};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn shared_with_slots() -> Arc<SharedXTState> {
+        let shared = Arc::new(SharedXTState::new());
+        shared.setup_slots(
+            HashSet::from(["slot1".to_string(), "slot2".to_string()]),
+            false,
+        );
+        shared
+    }
+
+    #[test]
+    fn test_wait_until_activated() {
+        let shared = shared_with_slots();
+
+        let waiter = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || shared.wait_until_activated())
+        };
+        let slot_waiter = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || shared.wait_for_slot("slot2", true))
+        };
+
+        shared.update_callback("slot1".to_string(), true);
+        thread::sleep(Duration::from_millis(10));
+        shared.update_callback("slot2".to_string(), true);
+
+        waiter.join().unwrap();
+        slot_waiter.join().unwrap().unwrap();
+        assert!(shared.read(|xt| xt.is_activated()));
+    }
+
+    #[test]
+    fn test_wait_until_activated_timeout() {
+        let shared = shared_with_slots();
+        shared.update_callback("slot1".to_string(), true);
+
+        assert!(!shared.wait_until_activated_timeout(Duration::from_millis(20)));
+        assert_eq!(
+            shared.wait_for_slot("unknown", true),
+            Err(XTStateError::UnknownSlot("unknown".to_string()))
+        );
+
+        shared.update_callback("slot2".to_string(), true);
+        assert!(shared.wait_until_activated_timeout(Duration::from_millis(20)));
+
+        let setup = SlotSetup::new()
+            .slots(["slot1".to_string()])
+            .values([("slot2".to_string(), true)]);
+        assert_eq!(
+            shared.try_setup(setup.clone()),
+            Err(XTStateError::AlreadySetUp)
+        );
+        shared.setup(setup.policy(ActivationPolicy::Any).force(true));
+        assert_eq!(shared.read(|xt| xt.run_id()), 2);
+        assert!(shared.wait_until_activated_timeout(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_listeners_run_outside_the_lock() {
+        let shared = shared_with_slots();
+        {
+            let observed = Arc::clone(&shared);
+            shared.on_activated(move |change| {
+                // Re-entering the state from a listener must not deadlock.
+                assert!(observed.read(|xt| xt.is_activated()));
+                assert_eq!(*change.slot, "slot2");
+            });
+        }
+
+        shared.update_callback("slot1".to_string(), true);
+        shared.update_callback("slot2".to_string(), true);
+        assert!(shared.read(|xt| xt.is_activated()));
+    }
+
+    #[test]
+    fn test_listener_updates_are_queued() {
+        let shared = shared_with_slots();
+        let activations = Arc::new(Mutex::new(Vec::new()));
+        {
+            let observed = Arc::clone(&shared);
+            let activations = Arc::clone(&activations);
+            shared.on_activated(move |change| {
+                activations.lock().unwrap().push(change.slot.to_string());
+                if *change.slot == "slot2" {
+                    // Applied right away, but its listeners only run once this one returns.
+                    observed.update_callback("slot1".to_string(), false);
+                    assert!(observed.read(|xt| !xt.is_activated()));
+                    assert_eq!(activations.lock().unwrap().len(), 1);
+                }
+            });
+        }
+        {
+            let observed = Arc::clone(&shared);
+            shared.on_deactivated(move |_| observed.update_callback("slot1".to_string(), true));
+        }
+
+        shared.update_callback("slot1".to_string(), true);
+        shared.update_callback("slot2".to_string(), true);
+        assert!(shared.read(|xt| xt.is_activated()));
+        assert_eq!(*activations.lock().unwrap(), ["slot2", "slot1"]);
+    }
+
+    #[test]
+    fn test_concurrent_updates_notify_in_order() {
+        let shared = Arc::new(SharedXTState::new());
+        shared.setup_slots(HashSet::from(["slot".to_string()]), false);
+        let edges = Arc::new(Mutex::new(Vec::new()));
+        for activated in [true, false] {
+            let edges = Arc::clone(&edges);
+            let listener = move |_: &SlotChange| edges.lock().unwrap().push(activated);
+            if activated {
+                shared.on_activated(listener);
+            } else {
+                shared.on_deactivated(listener);
+            }
+        }
+
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    for i in 0..1_000 {
+                        shared.update_callback("slot".to_string(), (i + thread) % 2 == 0);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let edges = edges.lock().unwrap();
+        assert!(edges.windows(2).all(|pair| pair[0] != pair[1]));
+        assert_eq!(
+            edges.last().copied().unwrap_or(false),
+            shared.read(|xt| xt.is_activated())
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{ActivationPolicy, Identifier, ValueRules};
+
+/// A direct reference to a slot, obtained from `XTState::handle`. Updating through a handle
+/// skips the identifier lookup entirely. Every slot insertion gets a fresh generation, so
+/// handles taken before a forced setup or before the slot was removed are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SlotHandle {
+    index: usize,
+    generation: u64,
+}
+
+/// The value a slot starts with at setup and the value it must hold to count as satisfied,
+/// overriding `ValueRules::is_satisfied` for that slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SlotSpec<V = bool> {
+    pub initial: V,
+    pub target: V,
+}
+
+impl<V> SlotSpec<V> {
+    pub fn new(initial: V, target: V) -> Self {
+        SlotSpec { initial, target }
+    }
+}
+
+/// The slots, activation policy and options for `XTState::setup`, e.g.
+/// `SlotSetup::new().slots(names).specs(specs).policy(policy)`. Slots are set up in the order
+/// they are listed; listing a slot again replaces its earlier entry and moves it to the end.
+#[derive(Debug, Clone)]
+pub struct SlotSetup<K = Identifier, V = bool> {
+    // Each slot's listing order, and its initial and target value, if any.
+    pub(crate) slots: HashMap<K, (usize, Option<V>, Option<V>)>,
+    listed: usize,
+    pub(crate) policy: ActivationPolicy<K>,
+    pub(crate) force: bool,
+    // Replays take initial values as the state the log starts from instead of recording them.
+    pub(crate) record_initial: bool,
+}
+
+impl<K: Eq + Hash, V> SlotSetup<K, V> {
+    /// No slots yet, the `All` policy, and no forced replacement of an existing setup.
+    pub fn new() -> Self {
+        SlotSetup {
+            slots: HashMap::new(),
+            listed: 0,
+            policy: ActivationPolicy::All,
+            force: false,
+            record_initial: true,
+        }
+    }
+
+    /// Slots that start at `ValueRules::initial`.
+    pub fn slots(mut self, slots: impl IntoIterator<Item = K>) -> Self {
+        for slot in slots {
+            self.list(slot, None, None);
+        }
+        self
+    }
+
+    /// Slots with already known values, e.g. when restoring after a restart. Each value is
+    /// recorded in the history as an update at setup time and counts towards activation
+    /// right away.
+    pub fn values(mut self, values: impl IntoIterator<Item = (K, V)>) -> Self {
+        for (slot, value) in values {
+            self.list(slot, Some(value), None);
+        }
+        self
+    }
+
+    /// Slots that are satisfied exactly when they hold their target, e.g.
+    /// `SlotSpec::new(true, false)` for a flag that must be switched off. Initial values are
+    /// recorded as with `values`.
+    pub fn specs(mut self, specs: impl IntoIterator<Item = (K, SlotSpec<V>)>) -> Self {
+        for (slot, spec) in specs {
+            self.list(slot, Some(spec.initial), Some(spec.target));
+        }
+        self
+    }
+
+    /// Slots that start at `ValueRules::initial` but are satisfied exactly when they hold
+    /// their target, e.g. to replay the history of slots set up with `specs`, which already
+    /// records their initial values.
+    pub fn targets(mut self, targets: impl IntoIterator<Item = (K, V)>) -> Self {
+        for (slot, target) in targets {
+            self.list(slot, None, Some(target));
+        }
+        self
+    }
+
+    pub fn policy(mut self, policy: ActivationPolicy<K>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Replace an existing setup, archiving its run, instead of failing with `AlreadySetUp`.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    fn list(&mut self, slot: K, initial: Option<V>, target: Option<V>) {
+        self.slots.insert(slot, (self.listed, initial, target));
+        self.listed += 1;
+    }
+}
+
+impl<K: Eq + Hash, V> Default for SlotSetup<K, V> {
+    fn default() -> Self {
+        SlotSetup::new()
+    }
+}
+
+pub(crate) struct Slot<K, V = bool> {
+    pub(crate) name: Arc<K>,
+    pub(crate) value: V,
+    pub(crate) target: Option<V>,
+    pub(crate) required: bool,
+    // Cached from the target or the state's `ValueRules` whenever the value changes.
+    pub(crate) satisfied: bool,
+    pub(crate) failed: bool,
+    generation: u64,
+}
+
+impl<K, V: PartialEq> Slot<K, V> {
+    /// Stores `value` and returns the previous one.
+    pub(crate) fn set(&mut self, value: V, rules: &ValueRules<V>) -> V {
+        self.satisfied = match &self.target {
+            Some(target) => value == *target,
+            None => rules.is_satisfied(&value),
+        };
+        self.failed = rules.is_failed(&value);
+        std::mem::replace(&mut self.value, value)
+    }
+}
+
+/// Compact slot storage: values live in a `Vec` addressed by index, with a side index from
+/// identifier to position. Removed positions are recycled through a free list.
+pub(crate) struct SlotTable<K, V = bool> {
+    index: HashMap<K, usize>,
+    entries: Vec<Option<Slot<K, V>>>,
+    free: Vec<usize>,
+    next_generation: u64,
+}
+
+impl<K, V> Default for SlotTable<K, V> {
+    fn default() -> Self {
+        SlotTable {
+            index: HashMap::new(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            next_generation: 0,
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone + PartialEq> SlotTable<K, V> {
+    pub(crate) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub(crate) fn contains<Q>(&self, identifier: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.index.contains_key(identifier)
+    }
+
+    pub(crate) fn position<Q>(&self, identifier: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.index.get(identifier).copied()
+    }
+
+    pub(crate) fn get<Q>(&self, identifier: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.position(identifier)
+            .map(|position| &self.slot(position).value)
+    }
+
+    pub(crate) fn is_satisfied<Q>(&self, identifier: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.position(identifier)
+            .is_some_and(|position| self.slot(position).satisfied)
+    }
+
+    pub(crate) fn slot(&self, position: usize) -> &Slot<K, V> {
+        self.entries[position]
+            .as_ref()
+            .expect("slot positions always point at live entries")
+    }
+
+    pub(crate) fn slot_mut(&mut self, position: usize) -> &mut Slot<K, V> {
+        self.entries[position]
+            .as_mut()
+            .expect("slot positions always point at live entries")
+    }
+
+    pub(crate) fn handle<Q>(&self, identifier: &Q) -> Option<SlotHandle>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.position(identifier).map(|index| SlotHandle {
+            index,
+            generation: self.slot(index).generation,
+        })
+    }
+
+    pub(crate) fn resolve(&self, handle: SlotHandle) -> Option<usize> {
+        match self.entries.get(handle.index) {
+            Some(Some(slot)) if slot.generation == handle.generation => Some(handle.index),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn handles(&self) -> impl Iterator<Item = (&Slot<K, V>, SlotHandle)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                entry.as_ref().map(|slot| {
+                    let handle = SlotHandle {
+                        index,
+                        generation: slot.generation,
+                    };
+                    (slot, handle)
+                })
+            })
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Slot<K, V>> {
+        self.entries.iter().flatten()
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        identifier: K,
+        value: V,
+        target: Option<V>,
+        required: bool,
+        rules: &ValueRules<V>,
+    ) -> usize {
+        self.next_generation += 1;
+        let mut slot = Slot {
+            name: Arc::new(identifier.clone()),
+            value: value.clone(),
+            target,
+            required,
+            satisfied: false,
+            failed: false,
+            generation: self.next_generation,
+        };
+        slot.set(value, rules);
+        let position = match self.free.pop() {
+            Some(position) => {
+                self.entries[position] = Some(slot);
+                position
+            }
+            None => {
+                self.entries.push(Some(slot));
+                self.entries.len() - 1
+            }
+        };
+        self.index.insert(identifier, position);
+        position
+    }
+
+    pub(crate) fn remove<Q>(&mut self, identifier: &Q) -> Option<Slot<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let position = self.index.remove(identifier)?;
+        self.free.push(position);
+        self.entries[position].take()
+    }
+
+    /// Drops every slot. Generations keep counting so that old handles stay invalid.
+    pub(crate) fn clear(&mut self) {
+        self.index.clear();
+        self.entries.clear();
+        self.free.clear();
+    }
+
+    pub(crate) fn targets(&self) -> HashMap<K, V> {
+        self.iter()
+            .filter_map(|slot| Some(((*slot.name).clone(), slot.target.clone()?)))
+            .collect()
+    }
+
+    pub(crate) fn to_map(&self) -> HashMap<K, V> {
+        self.iter()
+            .map(|slot| ((*slot.name).clone(), slot.value.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::policy::Tally;
+use crate::slots::SlotTable;
+use crate::{
+    ActivationPolicy, Evictions, History, HistoryEntry, Identifier, Retention, SlotValue,
+    ValueRules, XTState, XTStateError,
+};
+
+/// A plain-data copy of an `XTState`, suitable for persisting or shipping between processes.
+/// Listeners and archived runs are not part of a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "K: Serialize + Eq + Hash, V: Serialize",
+        deserialize = "K: Deserialize<'de> + Eq + Hash, V: Deserialize<'de>"
+    ))
+)]
+pub struct XTStateSnapshot<K: Eq + Hash = Identifier, V = bool> {
+    pub slots: HashMap<K, V>,
+    pub history: Vec<HistoryEntry<K, V>>,
+    pub is_setup: bool,
+    pub activated: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub policy: ActivationPolicy<K>,
+    /// Per-slot targets from `SlotSetup::specs`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub targets: HashMap<K, V>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub retention: Retention,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub evictions: Evictions,
+    /// Slot values before the first history entry; see `History::horizon`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub history_base: HashMap<K, V>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub history_horizon: Option<i64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub history_next_seq: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub run_id: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub run_started_at: Option<i64>,
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: Clone + PartialEq> XTStateSnapshot<K, V> {
+    /// Numbers entries from snapshots taken before sequence numbers existed, which come in
+    /// with `seq` 0, after the entry before them.
+    fn renumber(&mut self) {
+        let mut last = 0;
+        for entry in &mut self.history {
+            if entry.seq == 0 {
+                entry.seq = last + 1;
+            }
+            last = entry.seq;
+        }
+    }
+
+    fn validate(&self, rules: &ValueRules<V>) -> Result<SlotTable<K, V>, XTStateError<K>> {
+        if !self.is_setup {
+            if !self.slots.is_empty()
+                || !self.targets.is_empty()
+                || !self.history.is_empty()
+                || !self.history_base.is_empty()
+                || self.history_horizon.is_some()
+                || self.activated
+            {
+                return Err(invalid(
+                    "a state that is not set up cannot hold slots, history or activation",
+                ));
+            }
+            return Ok(SlotTable::default());
+        }
+
+        let mut next_seq = 1;
+        for entry in &self.history {
+            if entry.seq < next_seq {
+                return Err(invalid("history sequence numbers must strictly increase"));
+            }
+            next_seq = entry.seq + 1;
+        }
+        if self.history_next_seq != 0 && self.history_next_seq < next_seq {
+            return Err(invalid("the next sequence number is already in use"));
+        }
+
+        let mut last_values = HashMap::new();
+        for entry in &self.history {
+            last_values.insert(&entry.slot, entry.value());
+        }
+        for (identifier, value) in last_values {
+            match (self.slots.get(&**identifier), value) {
+                (None, Some(_)) => {
+                    return Err(invalid(format!(
+                        "history references unknown slot {:?}",
+                        identifier
+                    )));
+                }
+                (Some(_), None) => {
+                    return Err(invalid(format!(
+                        "slot {:?} is present although its history ends with a removal",
+                        identifier
+                    )));
+                }
+                (Some(current), Some(value)) if *current != value => {
+                    return Err(invalid(format!(
+                        "slot {:?} does not match its last history entry",
+                        identifier
+                    )));
+                }
+                _ => {}
+            }
+        }
+        if self.history_horizon.is_some() {
+            let mut replayed = self.history_base.clone();
+            for entry in &self.history {
+                match entry.value() {
+                    Some(value) => replayed.insert((*entry.slot).clone(), value),
+                    None => replayed.remove(&*entry.slot),
+                };
+            }
+            if replayed != self.slots {
+                return Err(invalid(
+                    "replaying the history from its base does not give the slot values",
+                ));
+            }
+        }
+
+        if let Some(unknown) = self
+            .targets
+            .keys()
+            .find(|slot| !self.slots.contains_key(*slot))
+        {
+            return Err(invalid(format!(
+                "target set for unknown slot {:?}",
+                unknown
+            )));
+        }
+        let names = self.slots.keys().cloned().collect();
+        self.policy
+            .validate(&names)
+            .map_err(|err| invalid(err.to_string()))?;
+        let slots = self.policy.slot_table(&self.slots, &self.targets, rules);
+        let expected = !slots.is_empty() && self.policy.is_met(&slots);
+        if self.activated != expected {
+            return Err(invalid("activated does not match the slot values"));
+        }
+        Ok(slots)
+    }
+}
+
+fn invalid<K>(reason: impl Into<String>) -> XTStateError<K> {
+    XTStateError::InvalidSnapshot(reason.into())
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: SlotValue> XTState<K, V> {
+    /// Rebuilds a state from a snapshot, rejecting snapshots whose fields contradict each other.
+    pub fn restore(snapshot: XTStateSnapshot<K, V>) -> Result<XTState<K, V>, XTStateError<K>> {
+        XTState::restore_with_rules(snapshot, ValueRules::default())
+    }
+}
+
+impl<K: Eq + Hash + Clone + fmt::Debug, V: Clone + PartialEq + fmt::Debug> XTState<K, V> {
+    pub fn snapshot(&self) -> XTStateSnapshot<K, V> {
+        XTStateSnapshot {
+            slots: self.slots.to_map(),
+            history: self.history.to_vec(),
+            is_setup: self.is_setup,
+            activated: self.activated,
+            policy: self.policy.clone(),
+            targets: self.slots.targets(),
+            retention: self.history.retention(),
+            evictions: self.history.evictions(),
+            history_base: self.history.base().clone(),
+            history_horizon: self.history.horizon(),
+            history_next_seq: self.history.next_seq(),
+            run_id: self.history.run(),
+            run_started_at: self.history.started_at(),
+        }
+    }
+
+    /// Like `restore`, for values interpreted by `rules`. Activation is checked against them.
+    pub fn restore_with_rules(
+        mut snapshot: XTStateSnapshot<K, V>,
+        rules: ValueRules<V>,
+    ) -> Result<XTState<K, V>, XTStateError<K>> {
+        snapshot.renumber();
+        let slots = snapshot.validate(&rules)?;
+        let mut xt_state = XTState::with_rules(rules);
+        xt_state.tally = Tally::new(&slots);
+        xt_state.slots = slots;
+        xt_state.history = History::restore(
+            snapshot.history,
+            snapshot.retention,
+            snapshot.evictions,
+            snapshot.history_base,
+            snapshot.history_horizon,
+            snapshot.history_next_seq,
+        )
+        // Snapshots taken before run ids existed still describe one set-up run.
+        .with_run(
+            snapshot.run_id.max(snapshot.is_setup as u64),
+            snapshot.run_started_at,
+        );
+        xt_state.is_setup = snapshot.is_setup;
+        xt_state.activated = snapshot.activated;
+        xt_state.policy = snapshot.policy;
+        Ok(xt_state)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K: Serialize + Eq + Hash + Clone + fmt::Debug, V: Serialize + Clone + PartialEq + fmt::Debug>
+    Serialize for XTState<K, V>
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, V> Deserialize<'de> for XTState<K, V>
+where
+    K: Deserialize<'de> + Eq + Hash + Clone + fmt::Debug,
+    V: Deserialize<'de> + SlotValue,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = XTStateSnapshot::deserialize(deserializer)?;
+        XTState::restore(snapshot).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HistoryEvent;
+    use std::collections::HashSet;
+
+    fn activated_state() -> XTState {
+        let mut xt_state = XTState::new();
+        xt_state.setup_slots(
+            HashSet::from(["slot1".to_string(), "slot2".to_string()]),
+            false,
+        );
+        xt_state.update_callback("slot1".to_string(), true);
+        xt_state.update_callback("slot2".to_string(), true);
+        xt_state
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let xt_state = activated_state();
+        let restored = XTState::restore(xt_state.snapshot()).unwrap();
+
+        assert!(restored.is_setup());
+        assert!(restored.is_activated());
+        assert_eq!(restored.history(), xt_state.history());
+        assert_eq!(restored.snapshot(), xt_state.snapshot());
+    }
+
+    #[test]
+    fn test_restore_rejects_inconsistent_snapshots() {
+        let mut snapshot = activated_state().snapshot();
+        snapshot.activated = false;
+        assert!(matches!(
+            XTState::restore(snapshot),
+            Err(XTStateError::InvalidSnapshot(_))
+        ));
+
+        let mut snapshot = activated_state().snapshot();
+        snapshot.history.push(HistoryEntry {
+            slot: std::sync::Arc::new("slot3".to_string()),
+            event: HistoryEvent::Updated(true),
+            previous: Some(false),
+            timestamp: 0,
+            seq: 3,
+            elapsed: std::time::Duration::ZERO,
+        });
+        assert!(matches!(
+            XTState::restore(snapshot),
+            Err(XTStateError::InvalidSnapshot(_))
+        ));
+
+        let mut snapshot = activated_state().snapshot();
+        snapshot.slots.insert("slot2".to_string(), false);
+        assert!(matches!(
+            XTState::restore(snapshot),
+            Err(XTStateError::InvalidSnapshot(_))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let xt_state = activated_state();
+        let json = serde_json::to_string(&xt_state).unwrap();
+        let restored: XTState = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.snapshot(), xt_state.snapshot());
+
+        let tampered = json.replace("\"activated\":true", "\"activated\":false");
+        assert!(serde_json::from_str::<XTState>(&tampered).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_restores_history_without_sequence_numbers() {
+        let mut json = serde_json::to_value(activated_state()).unwrap();
+        for entry in json["history"].as_array_mut().unwrap() {
+            let entry = entry.as_object_mut().unwrap();
+            entry.remove("seq");
+            entry.remove("elapsed");
+            entry.remove("previous");
+        }
+        json.as_object_mut().unwrap().remove("history_next_seq");
+
+        let mut restored: XTState = serde_json::from_value(json).unwrap();
+        restored.update_callback("slot1".to_string(), false);
+        let seqs: Vec<_> = restored.history().iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, [1, 2, 3]);
+    }
+}
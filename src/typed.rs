@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+
+/// A closed set of slots known at compile time, normally implemented with
+/// `#[derive(XtSlots)]` (requires the `derive` feature).
+///
+/// The derive also generates a `<Name>State` wrapper around `XTState<Self::Key>` with a
+/// `set_<slot>(bool)` setter per slot. Since the wrapper never adds or removes slots, an
+/// unknown identifier cannot reach `update_callback`:
+///
+#[cfg_attr(feature = "derive", doc = "```compile_fail")]
+#[cfg_attr(not(feature = "derive"), doc = "```ignore")]
+/// use xtstate::XtSlots;
+///
+/// #[derive(XtSlots)]
+/// struct Readiness {
+///     database: bool,
+/// }
+///
+/// let mut state = ReadinessState::new();
+/// state.set_cache(true);
+/// ```
+pub trait XtSlots {
+    type Key: Eq + Hash + Clone + fmt::Debug;
+
+    fn keys() -> HashSet<Self::Key>;
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use crate::{ActivationPolicy, XtSlots};
+
+    #[derive(XtSlots)]
+    struct Readiness {
+        database: bool,
+        fallback_cache: bool,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, XtSlots)]
+    enum Stage {
+        Fetch,
+        DeployCanary,
+    }
+
+    // The generated code must not pick up local items named like prelude ones.
+    mod shadowed {
+        use crate::XtSlots;
+
+        type Result<T> = std::result::Result<T, String>;
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, XtSlots)]
+        pub enum Check {
+            LoadTLSCerts,
+        }
+
+        pub fn ready() -> Result<bool> {
+            let mut state = CheckState::new();
+            state.set_load_tls_certs(true);
+            Ok(state.is_activated())
+        }
+    }
+
+    #[test]
+    fn test_struct_slots() {
+        assert_eq!(Readiness::keys().len(), 2);
+
+        let mut state = ReadinessState::new();
+        state.set_database(true);
+        assert!(!state.is_activated());
+        assert_eq!(
+            state.pending_slots().collect::<Vec<_>>(),
+            vec![&ReadinessSlot::FallbackCache]
+        );
+
+        state.set_fallback_cache(true);
+        assert!(state.is_activated());
+        let values = state.values();
+        assert!(values.database && values.fallback_cache);
+        assert_eq!(*state.history()[0].slot, ReadinessSlot::Database);
+    }
+
+    #[test]
+    fn test_enum_slots() {
+        let mut state = StageState::with_policy(ActivationPolicy::Any);
+        assert_eq!(
+            Stage::keys(),
+            state.slots().map(|(stage, _)| *stage).collect()
+        );
+
+        state.set_deploy_canary(true);
+        assert!(state.is_activated());
+        assert_eq!(state.get(&Stage::DeployCanary), Some(true));
+        assert!(StageState::try_with_policy(ActivationPolicy::AtLeast(3)).is_err());
+    }
+
+    #[test]
+    fn test_generated_code_is_hygienic() {
+        assert_eq!(shadowed::ready(), Ok(true));
+    }
+}
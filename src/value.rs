@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A value a slot can hold. Activation policies only look at whether each slot is satisfied
+/// and whether it has failed, so any value type can be tracked under any policy. For types
+/// this trait cannot be implemented on, describe them with `ValueRules` instead.
+pub trait SlotValue: Clone + PartialEq + fmt::Debug + 'static {
+    /// The value every slot starts with at setup.
+    fn initial() -> Self;
+
+    /// Whether the slot counts towards activation.
+    fn is_satisfied(&self) -> bool;
+
+    /// Whether the slot can no longer become satisfied without being updated again. A failed
+    /// slot that the policy cannot do without fails the whole state.
+    fn is_failed(&self) -> bool {
+        false
+    }
+
+    /// Completion between 0.0 and 1.0, aggregated by `XTState::progress`. Defaults to 1.0 for
+    /// satisfied values and 0.0 otherwise.
+    fn progress(&self) -> f64 {
+        if self.is_satisfied() { 1.0 } else { 0.0 }
+    }
+}
+
+impl SlotValue for bool {
+    fn initial() -> Self {
+        false
+    }
+
+    fn is_satisfied(&self) -> bool {
+        *self
+    }
+}
+
+/// The lifecycle of a workflow step.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SlotStatus {
+    #[default]
+    Pending,
+    InProgress,
+    Done,
+    Failed(String),
+    /// Counts as satisfied, like `Done`.
+    Skipped,
+}
+
+impl SlotValue for SlotStatus {
+    fn initial() -> Self {
+        SlotStatus::Pending
+    }
+
+    fn is_satisfied(&self) -> bool {
+        matches!(self, SlotStatus::Done | SlotStatus::Skipped)
+    }
+
+    fn is_failed(&self) -> bool {
+        matches!(self, SlotStatus::Failed(_))
+    }
+}
+
+/// Completion of a long-running step, as a fraction or as `done` out of `total` units.
+/// Satisfied once complete.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Progress {
+    Fraction(f64),
+    Count { done: u64, total: u64 },
+}
+
+// NaN fractions equal each other, so a slot holding one still matches its own snapshot.
+impl PartialEq for Progress {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Progress::Fraction(a), Progress::Fraction(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (
+                Progress::Count { done, total },
+                Progress::Count {
+                    done: other_done,
+                    total: other_total,
+                },
+            ) => done == other_done && total == other_total,
+            _ => false,
+        }
+    }
+}
+
+impl Progress {
+    /// The completed fraction, clamped to `0.0..=1.0`. An empty count is complete.
+    pub fn fraction(&self) -> f64 {
+        match *self {
+            Progress::Fraction(fraction) if fraction.is_nan() => 0.0,
+            Progress::Fraction(fraction) => fraction.clamp(0.0, 1.0),
+            Progress::Count { total: 0, .. } => 1.0,
+            Progress::Count { done, total } => (done as f64 / total as f64).min(1.0),
+        }
+    }
+}
+
+impl SlotValue for Progress {
+    fn initial() -> Self {
+        Progress::Fraction(0.0)
+    }
+
+    fn is_satisfied(&self) -> bool {
+        self.fraction() >= 1.0
+    }
+
+    fn progress(&self) -> f64 {
+        self.fraction()
+    }
+}
+
+type Test<V> = Arc<dyn Fn(&V) -> bool + Send + Sync>;
+
+/// How an `XTState` interprets its slot values: the value slots start with, and which values
+/// are satisfied, failed, or partially complete. The default follows `SlotValue`; build one
+/// with `ValueRules::new` to track types such as version strings or counters.
+pub struct ValueRules<V> {
+    initial: V,
+    satisfied: Test<V>,
+    failed: Test<V>,
+    progress: Arc<dyn Fn(&V) -> f64 + Send + Sync>,
+}
+
+impl<V: 'static> ValueRules<V> {
+    /// Slots start at `initial` and count towards activation while `satisfied` holds. Values
+    /// never fail, and their progress is 1.0 when satisfied and 0.0 otherwise.
+    pub fn new(initial: V, satisfied: impl Fn(&V) -> bool + Send + Sync + 'static) -> Self {
+        let satisfied: Test<V> = Arc::new(satisfied);
+        let progress = {
+            let satisfied = Arc::clone(&satisfied);
+            Arc::new(move |value: &V| if satisfied(value) { 1.0 } else { 0.0 })
+        };
+        ValueRules {
+            initial,
+            satisfied,
+            failed: Arc::new(|_| false),
+            progress,
+        }
+    }
+
+    pub fn with_failed(mut self, failed: impl Fn(&V) -> bool + Send + Sync + 'static) -> Self {
+        self.failed = Arc::new(failed);
+        self
+    }
+
+    pub fn with_progress(mut self, progress: impl Fn(&V) -> f64 + Send + Sync + 'static) -> Self {
+        self.progress = Arc::new(progress);
+        self
+    }
+}
+
+impl<V> ValueRules<V> {
+    pub fn initial(&self) -> &V {
+        &self.initial
+    }
+
+    pub fn is_satisfied(&self, value: &V) -> bool {
+        (self.satisfied)(value)
+    }
+
+    pub fn is_failed(&self, value: &V) -> bool {
+        (self.failed)(value)
+    }
+
+    pub fn progress(&self, value: &V) -> f64 {
+        (self.progress)(value)
+    }
+}
+
+impl<V: SlotValue> Default for ValueRules<V> {
+    fn default() -> Self {
+        ValueRules {
+            initial: V::initial(),
+            satisfied: Arc::new(V::is_satisfied),
+            failed: Arc::new(V::is_failed),
+            progress: Arc::new(V::progress),
+        }
+    }
+}
+
+// Derived `Clone` and `Debug` would need the predicates to implement them too.
+impl<V: Clone> Clone for ValueRules<V> {
+    fn clone(&self) -> Self {
+        ValueRules {
+            initial: self.initial.clone(),
+            satisfied: Arc::clone(&self.satisfied),
+            failed: Arc::clone(&self.failed),
+            progress: Arc::clone(&self.progress),
+        }
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for ValueRules<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValueRules")
+            .field("initial", &self.initial)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Weighted mean of per-slot progress; slots missing from `weights` weigh 1.0.
+pub(crate) fn weighted_progress<'a, K: Eq + Hash + 'a>(
+    slots: impl IntoIterator<Item = (&'a K, f64)>,
+    weights: &HashMap<K, f64>,
+) -> f64 {
+    let (mut done, mut total) = (0.0, 0.0);
+    for (slot, progress) in slots {
+        let weight = weights.get(slot).copied().unwrap_or(1.0);
+        done += weight * progress;
+        total += weight;
+    }
+    if total > 0.0 { done / total } else { 0.0 }
+}
+
+/// The aggregate state of an `XTState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ActivationState {
+    /// The activation policy is met.
+    Activated,
+    /// The policy is not met yet, but could still be.
+    Waiting,
+    /// Failed slots make the policy unmeetable until one of them is updated.
+    Failed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HistoryEvent, ManualClock, SlotSetup, XTState};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_status_workflow() {
+        let mut xt_state: XTState<String, SlotStatus> = XTState::new();
+        xt_state.setup_slots(
+            HashSet::from(["build".to_string(), "deploy".to_string()]),
+            false,
+        );
+        assert_eq!(xt_state.get("build"), Some(SlotStatus::Pending));
+        assert_eq!(xt_state.state(), ActivationState::Waiting);
+
+        xt_state.update_callback("build".to_string(), SlotStatus::InProgress);
+        xt_state.update_callback(
+            "build".to_string(),
+            SlotStatus::Failed("exit 1".to_string()),
+        );
+        assert_eq!(xt_state.state(), ActivationState::Failed);
+        assert_eq!(xt_state.failed_slots().collect::<Vec<_>>(), ["build"]);
+
+        // Retrying the failed step leaves the failed state.
+        xt_state.update_callback("build".to_string(), SlotStatus::InProgress);
+        assert_eq!(xt_state.state(), ActivationState::Waiting);
+        xt_state.update_callback("build".to_string(), SlotStatus::Done);
+        xt_state.update_callback("deploy".to_string(), SlotStatus::Skipped);
+        assert_eq!(xt_state.state(), ActivationState::Activated);
+
+        let failure = &xt_state.history()[1];
+        assert_eq!(failure.previous, Some(SlotStatus::InProgress));
+        assert_eq!(
+            failure.event,
+            HistoryEvent::Updated(SlotStatus::Failed("exit 1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_progress_and_eta() {
+        let clock = Arc::new(ManualClock::new(0));
+        let mut xt_state: XTState<String, Progress> = XTState::with_clock(clock.clone());
+        xt_state.setup_slots(
+            HashSet::from(["copy".to_string(), "index".to_string()]),
+            false,
+        );
+        assert_eq!(xt_state.eta(), None);
+
+        clock.set(60_000);
+        xt_state.update_callback("copy".to_string(), Progress::Count { done: 3, total: 4 });
+        xt_state.update_callback("index".to_string(), Progress::Fraction(0.25));
+        assert_eq!(xt_state.progress(), 0.5);
+        let weights = HashMap::from([("copy".to_string(), 3.0)]);
+        assert_eq!(xt_state.weighted_progress(&weights), 0.625);
+        assert_eq!(xt_state.eta(), Some(Duration::from_secs(60)));
+
+        xt_state.update_callback("copy".to_string(), Progress::Count { done: 4, total: 4 });
+        assert_eq!(xt_state.pending_slots().collect::<Vec<_>>(), ["index"]);
+        xt_state.update_callback("index".to_string(), Progress::Fraction(1.0));
+        assert!(xt_state.is_activated());
+        assert_eq!(xt_state.eta(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_nan_progress_round_trips() {
+        let mut xt_state: XTState<String, Progress> = XTState::new();
+        xt_state.setup_slots(HashSet::from(["copy".to_string()]), false);
+        xt_state.update_callback("copy".to_string(), Progress::Fraction(f64::NAN));
+        assert_eq!(xt_state.progress(), 0.0);
+
+        let restored = XTState::restore(xt_state.snapshot()).unwrap();
+        assert_eq!(restored.get("copy"), Some(Progress::Fraction(f64::NAN)));
+        assert_ne!(Progress::Fraction(f64::NAN), Progress::Fraction(0.0));
+    }
+
+    #[test]
+    fn test_eta_ignores_initial_values() {
+        let clock = Arc::new(ManualClock::new(0));
+        let mut xt_state: XTState<String, Progress> = XTState::with_clock(clock.clone());
+        xt_state.setup(SlotSetup::new().values([("copy".to_string(), Progress::Fraction(0.5))]));
+        assert_eq!(xt_state.eta(), None);
+
+        clock.set(60_000);
+        xt_state.update_callback("copy".to_string(), Progress::Fraction(0.51));
+        let eta = xt_state.eta().unwrap().as_secs_f64();
+        assert!((eta - 2_940.0).abs() < 1.0, "{}", eta);
+
+        // A slot added later counts from the initial value rather than skewing the start.
+        xt_state.add_slot("index".to_string(), Progress::Fraction(0.0));
+        let eta = xt_state.eta().unwrap().as_secs_f64();
+        assert!((eta - 8_940.0).abs() < 1.0, "{}", eta);
+    }
+
+    #[test]
+    fn test_custom_slot_value() {
+        #[derive(Debug, Clone, PartialEq)]
+        enum Health {
+            Unknown,
+            Up,
+            Down,
+        }
+
+        impl SlotValue for Health {
+            fn initial() -> Self {
+                Health::Unknown
+            }
+
+            fn is_satisfied(&self) -> bool {
+                *self == Health::Up
+            }
+
+            fn is_failed(&self) -> bool {
+                *self == Health::Down
+            }
+        }
+
+        let mut xt_state: XTState<String, Health> = XTState::new();
+        xt_state.setup_slots(HashSet::from(["api".to_string()]), false);
+        xt_state.update_callback("api".to_string(), Health::Down);
+        assert_eq!(xt_state.state(), ActivationState::Failed);
+        xt_state.update_callback("api".to_string(), Health::Up);
+        assert!(xt_state.is_activated());
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        use crate::SharedXTState;
+
+        let major = |version: &str| version.split('.').next()?.parse::<u32>().ok();
+        let rules = ValueRules::new(String::new(), move |version: &String| {
+            major(version).is_some_and(|major| major >= 2)
+        })
+        .with_failed(|version| version == "broken");
+        let mut xt_state = XTState::with_rules(rules.clone());
+        xt_state.setup_slots(
+            HashSet::from(["api".to_string(), "worker".to_string()]),
+            false,
+        );
+        xt_state.update_callback("api".to_string(), "2.1".to_string());
+        xt_state.update_callback("worker".to_string(), "broken".to_string());
+        assert_eq!(xt_state.state(), ActivationState::Failed);
+
+        let snapshot = xt_state.snapshot();
+        let log = xt_state.history().to_vec();
+        let slots = HashSet::from(["api".to_string(), "worker".to_string()]);
+        assert_eq!(
+            snapshot.verify_with_rules(slots.clone(), rules.clone(), log.clone()),
+            Ok(())
+        );
+        let replayed =
+            XTState::replay_with_rules(SlotSetup::new().slots(slots), rules.clone(), log).unwrap();
+        assert_eq!(replayed.state(), ActivationState::Failed);
+
+        let restored = XTState::restore_with_rules(snapshot, rules).unwrap();
+        assert_eq!(restored.failed_slots().collect::<Vec<_>>(), ["worker"]);
+
+        let shared = SharedXTState::from(restored);
+        shared.update_callback("worker".to_string(), "2.0".to_string());
+        assert!(shared.read(|xt| xt.is_activated()));
+        assert_eq!(shared.read(|xt| xt.history().len()), 3);
+    }
+}
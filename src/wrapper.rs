@@ -0,0 +1,157 @@
+/// Expands, inside an `impl` block of a shared wrapper around `XTState`, to the API for
+/// registering listeners and changing the state. The wrapper provides two private methods
+/// that every operation goes through:
+///
+/// - `write(f)` runs `f` on the locked state;
+/// - `apply_with(f)` runs an `apply*` method of the locked state through `f` and hands the
+///   `Notice` it returns to the wrapper's `DispatchQueue`.
+macro_rules! mutating_api {
+    () => {
+        pub fn on_slot_change(
+            &self,
+            listener: impl Fn(&$crate::SlotChange<K, V>) + Send + Sync + 'static,
+        ) {
+            self.write(|xt| xt.on_slot_change(listener));
+        }
+
+        pub fn on_activated(
+            &self,
+            listener: impl Fn(&$crate::SlotChange<K, V>) + Send + Sync + 'static,
+        ) {
+            self.write(|xt| xt.on_activated(listener));
+        }
+
+        pub fn on_deactivated(
+            &self,
+            listener: impl Fn(&$crate::SlotChange<K, V>) + Send + Sync + 'static,
+        ) {
+            self.write(|xt| xt.on_deactivated(listener));
+        }
+
+        pub fn setup_slots(&self, slots: ::std::collections::HashSet<K>, force: bool) {
+            if let Err(err) = self.try_setup_slots(slots, force) {
+                panic!("{}", err);
+            }
+        }
+
+        pub fn try_setup_slots(
+            &self,
+            slots: ::std::collections::HashSet<K>,
+            force: bool,
+        ) -> Result<(), $crate::XTStateError<K>> {
+            self.try_setup_slots_with_policy(slots, $crate::ActivationPolicy::All, force)
+        }
+
+        pub fn setup_slots_with_policy(
+            &self,
+            slots: ::std::collections::HashSet<K>,
+            policy: $crate::ActivationPolicy<K>,
+            force: bool,
+        ) {
+            if let Err(err) = self.try_setup_slots_with_policy(slots, policy, force) {
+                panic!("{}", err);
+            }
+        }
+
+        pub fn try_setup_slots_with_policy(
+            &self,
+            slots: ::std::collections::HashSet<K>,
+            policy: $crate::ActivationPolicy<K>,
+            force: bool,
+        ) -> Result<(), $crate::XTStateError<K>> {
+            self.try_setup(
+                $crate::SlotSetup::new()
+                    .slots(slots)
+                    .policy(policy)
+                    .force(force),
+            )
+        }
+
+        pub fn setup(&self, setup: $crate::SlotSetup<K, V>) {
+            if let Err(err) = self.try_setup(setup) {
+                panic!("{}", err);
+            }
+        }
+
+        pub fn try_setup(
+            &self,
+            setup: $crate::SlotSetup<K, V>,
+        ) -> Result<(), $crate::XTStateError<K>> {
+            self.apply_with(|xt| xt.apply_setup(setup).map($crate::observer::Notice::Setup))
+        }
+
+        pub fn update_callback(&self, identifier: K, value: V) {
+            if let Err(err) = self.try_update(identifier, value) {
+                panic!("{}", err);
+            }
+        }
+
+        pub fn try_update(&self, identifier: K, value: V) -> Result<(), $crate::XTStateError<K>> {
+            self.apply_with(|xt| {
+                xt.apply(identifier, value)
+                    .map($crate::observer::Notice::Update)
+            })
+        }
+
+        pub fn update_by_handle(&self, handle: $crate::SlotHandle, value: V) {
+            if let Err(err) = self.try_update_by_handle(handle, value) {
+                panic!("{}", err);
+            }
+        }
+
+        pub fn try_update_by_handle(
+            &self,
+            handle: $crate::SlotHandle,
+            value: V,
+        ) -> Result<(), $crate::XTStateError<K>> {
+            self.apply_with(|xt| {
+                xt.apply_by_handle(handle, value)
+                    .map($crate::observer::Notice::Update)
+            })
+        }
+
+        pub fn add_slot(&self, identifier: K, initial: V) {
+            if let Err(err) = self.try_add_slot(identifier, initial) {
+                panic!("{}", err);
+            }
+        }
+
+        pub fn try_add_slot(
+            &self,
+            identifier: K,
+            initial: V,
+        ) -> Result<(), $crate::XTStateError<K>> {
+            self.apply_with(|xt| {
+                xt.apply_add(identifier, initial)
+                    .map($crate::observer::Notice::Update)
+            })
+        }
+
+        pub fn remove_slot<Q>(&self, identifier: &Q) -> V
+        where
+            K: ::std::borrow::Borrow<Q>,
+            Q: ::std::hash::Hash + Eq + ToOwned<Owned = K> + ?Sized,
+        {
+            match self.try_remove_slot(identifier) {
+                Ok(value) => value,
+                Err(err) => panic!("{}", err),
+            }
+        }
+
+        pub fn try_remove_slot<Q>(&self, identifier: &Q) -> Result<V, $crate::XTStateError<K>>
+        where
+            K: ::std::borrow::Borrow<Q>,
+            Q: ::std::hash::Hash + Eq + ToOwned<Owned = K> + ?Sized,
+        {
+            let mut removed = None;
+            self.apply_with(|xt| {
+                let update = xt.apply_remove(identifier)?;
+                removed = Some(update.change.old.clone());
+                Ok($crate::observer::Notice::Update(update))
+            })?;
+            Ok(removed.expect("a removal reports the removed value"))
+        }
+    };
+}
+
+pub(crate) use mutating_api;
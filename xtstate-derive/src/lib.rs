@@ -0,0 +1,369 @@
+//! Derive macro for `xtstate::XtSlots`. Use it through the `derive` feature of `xtstate`
+//! rather than depending on this crate directly.
+
+use std::collections::HashMap;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::ext::IdentExt;
+use syn::{Data, DeriveInput, Error, Fields, Ident, Type, parse_macro_input};
+
+/// Implements `xtstate::XtSlots` and generates a `<Name>State` wrapper with one
+/// `set_<slot>(value)` setter per slot.
+///
+/// On a struct of `bool` fields, every field becomes a slot: a `<Name>Slot` enum is generated
+/// as the key type and the wrapper gains `values()`, which reads the slots back into the struct.
+/// On a fieldless enum, every variant is a slot and the enum itself is the key type.
+#[proc_macro_derive(XtSlots)]
+pub fn derive_xt_slots(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "XtSlots cannot be derived for generic types",
+        ));
+    }
+    match &input.data {
+        Data::Struct(data) => expand_struct(input, &data.fields),
+        Data::Enum(data) => {
+            let mut variants = Vec::new();
+            for variant in &data.variants {
+                if !matches!(variant.fields, Fields::Unit) || variant.discriminant.is_some() {
+                    return Err(Error::new_spanned(
+                        variant,
+                        "XtSlots enums must be fieldless, without explicit discriminants",
+                    ));
+                }
+                variants.push(variant.ident.clone());
+            }
+            expand_enum(input, &variants)
+        }
+        Data::Union(_) => Err(Error::new(
+            Span::call_site(),
+            "XtSlots can only be derived for structs of bool fields or fieldless enums",
+        )),
+    }
+}
+
+fn expand_struct(input: &DeriveInput, fields: &Fields) -> syn::Result<TokenStream2> {
+    let Fields::Named(fields) = fields else {
+        return Err(Error::new_spanned(
+            fields,
+            "XtSlots structs must have named bool fields",
+        ));
+    };
+    let mut names = Vec::new();
+    for field in &fields.named {
+        if !is_bool(&field.ty) {
+            return Err(Error::new_spanned(&field.ty, "XtSlots fields must be bool"));
+        }
+        names.push(field.ident.clone().expect("named fields have identifiers"));
+    }
+    if names.is_empty() {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "XtSlots needs at least one field",
+        ));
+    }
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let key = format_ident!("{}Slot", name);
+    let mut variants = Vec::new();
+    for field in &names {
+        let variant = camel_case(&field.unraw().to_string());
+        match syn::parse_str::<Ident>(&variant) {
+            Ok(variant) => variants.push(variant),
+            Err(_) => {
+                return Err(Error::new(
+                    field.span(),
+                    format!("`{}` does not give a valid slot variant name", field),
+                ));
+            }
+        }
+    }
+    check_unique(&names, &variants)?;
+    let setters = setters(&key, &names, &variants);
+    let wrapper = wrapper(input, &key, &setters);
+    let state = format_ident!("{}State", name);
+
+    Ok(quote! {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #vis enum #key {
+            #(#variants,)*
+        }
+
+        impl ::xtstate::XtSlots for #name {
+            type Key = #key;
+
+            fn keys() -> ::std::collections::HashSet<#key> {
+                ::std::collections::HashSet::from([#(#key::#variants),*])
+            }
+        }
+
+        #wrapper
+
+        impl #state {
+            /// Reads the current slot values back into the struct.
+            pub fn values(&self) -> #name {
+                #name {
+                    #(#names: self.0.get(&#key::#variants).unwrap_or(false),)*
+                }
+            }
+        }
+    })
+}
+
+fn expand_enum(input: &DeriveInput, variants: &[Ident]) -> syn::Result<TokenStream2> {
+    if variants.is_empty() {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "XtSlots needs at least one variant",
+        ));
+    }
+    let name = &input.ident;
+    let fields: Vec<Ident> = variants
+        .iter()
+        .map(|variant| format_ident!("{}", snake_case(&variant.unraw().to_string())))
+        .collect();
+    check_unique(variants, &fields)?;
+    let setters = setters(name, &fields, variants);
+    let wrapper = wrapper(input, name, &setters);
+
+    Ok(quote! {
+        impl ::xtstate::XtSlots for #name {
+            type Key = #name;
+
+            fn keys() -> ::std::collections::HashSet<#name> {
+                ::std::collections::HashSet::from([#(#name::#variants),*])
+            }
+        }
+
+        #wrapper
+    })
+}
+
+fn setters(key: &Ident, fields: &[Ident], variants: &[Ident]) -> TokenStream2 {
+    let setters = fields.iter().zip(variants).map(|(field, variant)| {
+        let setter = format_ident!("set_{}", field.unraw());
+        quote! {
+            pub fn #setter(&mut self, value: bool) {
+                self.0.update_callback(#key::#variant, value);
+            }
+        }
+    });
+    quote! { #(#setters)* }
+}
+
+fn wrapper(input: &DeriveInput, key: &Ident, setters: &TokenStream2) -> TokenStream2 {
+    let vis = &input.vis;
+    let name = &input.ident;
+    let state = format_ident!("{}State", name);
+    let doc = format!(
+        "An `XTState` over the slots of `{}`. Slots cannot be added or removed, \
+         so every setter always targets a defined slot.",
+        name
+    );
+
+    quote! {
+        #[doc = #doc]
+        #[derive(Debug)]
+        #vis struct #state(::xtstate::XTState<#key>);
+
+        impl #state {
+            pub fn new() -> Self {
+                Self::with_policy(::xtstate::ActivationPolicy::All)
+            }
+
+            pub fn with_policy(policy: ::xtstate::ActivationPolicy<#key>) -> Self {
+                match Self::try_with_policy(policy) {
+                    ::core::result::Result::Ok(state) => state,
+                    ::core::result::Result::Err(err) => ::core::panic!("{}", err),
+                }
+            }
+
+            pub fn try_with_policy(
+                policy: ::xtstate::ActivationPolicy<#key>,
+            ) -> ::core::result::Result<Self, ::xtstate::XTStateError<#key>> {
+                let mut state = ::xtstate::XTState::new();
+                state.try_setup_slots_with_policy(
+                    <#name as ::xtstate::XtSlots>::keys(),
+                    policy,
+                    false,
+                )?;
+                ::core::result::Result::Ok(#state(state))
+            }
+
+            #setters
+
+            pub fn on_slot_change(
+                &mut self,
+                listener: impl ::core::ops::Fn(&::xtstate::SlotChange<#key>)
+                    + ::core::marker::Send
+                    + ::core::marker::Sync
+                    + 'static,
+            ) {
+                self.0.on_slot_change(listener);
+            }
+
+            pub fn on_activated(
+                &mut self,
+                listener: impl ::core::ops::Fn(&::xtstate::SlotChange<#key>)
+                    + ::core::marker::Send
+                    + ::core::marker::Sync
+                    + 'static,
+            ) {
+                self.0.on_activated(listener);
+            }
+
+            pub fn on_deactivated(
+                &mut self,
+                listener: impl ::core::ops::Fn(&::xtstate::SlotChange<#key>)
+                    + ::core::marker::Send
+                    + ::core::marker::Sync
+                    + 'static,
+            ) {
+                self.0.on_deactivated(listener);
+            }
+
+            pub fn into_inner(self) -> ::xtstate::XTState<#key> {
+                self.0
+            }
+        }
+
+        impl ::std::default::Default for #state {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl ::std::ops::Deref for #state {
+            type Target = ::xtstate::XTState<#key>;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+    }
+}
+
+/// Rejects two slots whose names convert to the same identifier, e.g. fields `a_1` and `a1`,
+/// pointing at the second one.
+fn check_unique(names: &[Ident], converted: &[Ident]) -> syn::Result<()> {
+    let mut seen = HashMap::new();
+    for (name, converted) in names.iter().zip(converted) {
+        if let Some(first) = seen.insert(converted.to_string(), name) {
+            return Err(Error::new(
+                name.span(),
+                format!("`{}` and `{}` both map to `{}`", first, name, converted),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn is_bool(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.qself.is_none() && path.path.is_ident("bool"))
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().map(|c| c.to_ascii_uppercase());
+            first.into_iter().chain(chars).collect::<String>()
+        })
+        .collect()
+}
+
+/// Converts a variant name to snake case, keeping runs of capitals together as one word:
+/// `HTTPServer` becomes `http_server`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() {
+            let word_start = i > 0
+                && match chars[i - 1] {
+                    '_' => false,
+                    previous if previous.is_ascii_uppercase() => {
+                        chars.get(i + 1).is_some_and(char::is_ascii_lowercase)
+                    }
+                    _ => true,
+                };
+            if word_start {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    fn error(input: DeriveInput) -> String {
+        expand(&input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_colliding_names_are_rejected() {
+        assert_eq!(
+            error(parse_quote! {
+                struct Flags {
+                    a_1: bool,
+                    a1: bool,
+                }
+            }),
+            "`a_1` and `a1` both map to `A1`"
+        );
+        assert_eq!(
+            error(parse_quote! {
+                struct Flags {
+                    x: bool,
+                    _x: bool,
+                }
+            }),
+            "`x` and `_x` both map to `X`"
+        );
+        assert_eq!(
+            error(parse_quote! {
+                enum Stage {
+                    HTTPServer,
+                    HttpServer,
+                }
+            }),
+            "`HTTPServer` and `HttpServer` both map to `http_server`"
+        );
+        assert_eq!(
+            error(parse_quote! {
+                struct Flags {
+                    __: bool,
+                }
+            }),
+            "`__` does not give a valid slot variant name"
+        );
+    }
+
+    #[test]
+    fn test_snake_case_groups_acronyms() {
+        assert_eq!(snake_case("DbReady"), "db_ready");
+        assert_eq!(snake_case("HTTPServer"), "http_server");
+        assert_eq!(snake_case("LoadJSON"), "load_json");
+        assert_eq!(snake_case("Http2Ready"), "http2_ready");
+        assert_eq!(snake_case("A_b"), "a_b");
+    }
+}